@@ -0,0 +1,66 @@
+use std::sync::OnceLock;
+
+use crate::config_file::setting;
+use crate::featured::normalize_keyword;
+
+static BANNED_WORDS: OnceLock<Vec<String>> = OnceLock::new();
+
+// Terms that refuse a query before any search runs, read from BANNED_QUERY_WORDS
+// (comma-separated) and normalized like featured keywords
+pub fn banned_words() -> &'static [String] {
+  BANNED_WORDS.get_or_init(|| {
+    setting("BANNED_QUERY_WORDS")
+      .map(|value| {
+        value
+          .split(',')
+          .map(normalize_keyword)
+          .filter(|word| !word.is_empty())
+          .collect()
+      })
+      .unwrap_or_default()
+  })
+}
+
+// The first banned term in the query, if any. Matching ignores case, accents and
+// hiragana/katakana; ASCII terms must match whole words (or word sequences), while CJK
+// terms match anywhere since those queries often have no spaces.
+pub fn find_banned_word<'a>(query: &str, banned_words: &'a [String]) -> Option<&'a str> {
+  if banned_words.is_empty() {
+    return None;
+  }
+
+  let query = normalize_keyword(query);
+  let padded = format!(" {} ", query);
+  banned_words
+    .iter()
+    .find(|word| {
+      if word.is_ascii() {
+        padded.contains(&format!(" {} ", word))
+      } else {
+        query.contains(word.as_str())
+      }
+    })
+    .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn banned_query_is_refused_and_clean_one_proceeds() {
+    let banned: Vec<String> = ["Gore", "nsfw art", "血腥"]
+      .into_iter()
+      .map(normalize_keyword)
+      .collect();
+
+    assert_eq!(find_banned_word("GORE  pics", &banned), Some("gore"));
+    assert_eq!(find_banned_word("cute nsfw art", &banned), Some("nsfw art"));
+    assert_eq!(find_banned_word("超血腥圖片", &banned), Some("血腥"));
+
+    // Whole ASCII words only, so unrelated queries still search
+    assert_eq!(find_banned_word("gorilla", &banned), None);
+    assert_eq!(find_banned_word("cat", &banned), None);
+    assert_eq!(find_banned_word("gore", &[]), None);
+  }
+}
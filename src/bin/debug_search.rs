@@ -1,7 +1,7 @@
 // Debug binary to test image search engines
 // Run with: RUST_LOG=info cargo run --bin debug_search
 
-use tgjpg_rs::image_search::ddg;
+use tgjpg_rs::image_search::{ddg, SearchLocale};
 
 #[tokio::main]
 async fn main() {
@@ -11,7 +11,7 @@ async fn main() {
   println!("Query: cat, is_gif: false");
   println!();
 
-  match ddg::search("cat", false).await {
+  match ddg::search("cat", false, &SearchLocale::default()).await {
     Ok(urls) => {
       println!("SUCCESS: Got {} URLs", urls.len());
       for (i, url) in urls.iter().enumerate() {
@@ -0,0 +1,22 @@
+// The bot's own identity, fetched once with get_me() at startup and injected into handlers
+#[derive(Clone, Debug)]
+pub struct BotInfo {
+  pub username: String,
+}
+
+impl BotInfo {
+  // Strip an "@botname" suffix from a command ("/start@my_bot" -> "/start").
+  // Returns None when the command is addressed to a different bot.
+  pub fn strip_command_suffix<'a>(&self, command: &'a str) -> Option<&'a str> {
+    match command.split_once('@') {
+      Some((command, username)) if username.eq_ignore_ascii_case(&self.username) => Some(command),
+      Some(_) => None,
+      None => Some(command),
+    }
+  }
+
+  // Replace the "@botname" placeholder in user-facing text with the real username
+  pub fn fill_username(&self, text: &str) -> String {
+    text.replace("@botname", &format!("@{}", self.username))
+  }
+}
@@ -0,0 +1,173 @@
+use log::info;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+use teloxide::types::{ChatId, Update, UpdateKind, User, UserId};
+
+use crate::config_file::setting;
+
+static ALLOWED_CHATS: OnceLock<Vec<ChatId>> = OnceLock::new();
+static DENIED_CHATS: OnceLock<Vec<ChatId>> = OnceLock::new();
+static INLINE_ALLOWED_USERS: OnceLock<Vec<UserId>> = OnceLock::new();
+static ADMIN_USERS: OnceLock<Vec<UserId>> = OnceLock::new();
+static LOGGED_CHATS: OnceLock<Mutex<HashSet<ChatId>>> = OnceLock::new();
+
+// Chats listed in DENIED_CHATS are never served. When ALLOWED_CHATS is non-empty,
+// only those chats are served; an empty allowlist serves everyone.
+pub fn is_chat_allowed(chat_id: ChatId) -> bool {
+  let allowed = ALLOWED_CHATS.get_or_init(|| read_chat_list("ALLOWED_CHATS"));
+  let denied = DENIED_CHATS.get_or_init(|| read_chat_list("DENIED_CHATS"));
+
+  is_listed_chat_allowed(chat_id, allowed, denied)
+}
+
+fn is_listed_chat_allowed(chat_id: ChatId, allowed: &[ChatId], denied: &[ChatId]) -> bool {
+  !denied.contains(&chat_id) && (allowed.is_empty() || allowed.contains(&chat_id))
+}
+
+// Inline mode can leak a private setup to anyone. INLINE_ALLOWED_USERS, when set, decides
+// alone; otherwise the chat lists are applied to the sender's user ID.
+pub fn is_inline_user_allowed(user_id: UserId) -> bool {
+  let inline_allowed = INLINE_ALLOWED_USERS.get_or_init(|| read_user_list("INLINE_ALLOWED_USERS"));
+
+  is_listed_inline_user_allowed(user_id, inline_allowed, is_chat_allowed)
+}
+
+fn is_listed_inline_user_allowed(
+  user_id: UserId,
+  inline_allowed: &[UserId],
+  is_chat_allowed: impl Fn(ChatId) -> bool,
+) -> bool {
+  if inline_allowed.is_empty() {
+    is_chat_allowed(ChatId::from(user_id))
+  } else {
+    inline_allowed.contains(&user_id)
+  }
+}
+
+// Operator commands (/preview, /flushcache, /maintenance and the like) are only run for the
+// users in ADMIN_USER_IDS. Unset, nobody is an admin; messages without a sender never are.
+pub fn is_admin(user: Option<&User>) -> bool {
+  let admins = ADMIN_USERS.get_or_init(|| read_user_list("ADMIN_USER_IDS"));
+
+  is_listed_admin(user, admins)
+}
+
+fn is_listed_admin(user: Option<&User>, admins: &[UserId]) -> bool {
+  user.is_some_and(|user| admins.contains(&user.id))
+}
+
+// Dispatcher filter: check the update's chat, or the sender for chatless updates.
+// Inline queries pass through so the handler can answer them with empty results.
+pub fn is_update_allowed(update: Update) -> bool {
+  if matches!(update.kind, UpdateKind::InlineQuery(_)) {
+    return true;
+  }
+
+  let chat_id = match (update.chat(), update.from()) {
+    (Some(chat), _) => chat.id,
+    (None, Some(user)) => ChatId::from(user.id),
+    (None, None) => return true,
+  };
+
+  let allowed = is_chat_allowed(chat_id);
+  if !allowed && first_rejection(chat_id) {
+    info!("Ignoring updates from unauthorized chat {}", chat_id);
+  }
+  allowed
+}
+
+fn first_rejection(chat_id: ChatId) -> bool {
+  LOGGED_CHATS
+    .get_or_init(|| Mutex::new(HashSet::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+    .insert(chat_id)
+}
+
+fn read_chat_list(key: &str) -> Vec<ChatId> {
+  setting(key)
+    .map(|value| {
+      value
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .map(ChatId)
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn read_user_list(key: &str) -> Vec<UserId> {
+  setting(key)
+    .map(|value| {
+      value
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .map(UserId)
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn empty_allowlist_serves_everyone_but_denied_chats() {
+    let denied = [ChatId(-2)];
+    assert!(is_listed_chat_allowed(ChatId(1), &[], &denied));
+    assert!(!is_listed_chat_allowed(ChatId(-2), &[], &denied));
+  }
+
+  #[test]
+  fn allowlist_limits_chats_and_deny_wins() {
+    let allowed = [ChatId(1), ChatId(-2)];
+    let denied = [ChatId(-2)];
+    assert!(is_listed_chat_allowed(ChatId(1), &allowed, &denied));
+    assert!(!is_listed_chat_allowed(ChatId(3), &allowed, &denied));
+    assert!(!is_listed_chat_allowed(ChatId(-2), &allowed, &denied));
+  }
+
+  #[test]
+  fn inline_allowlist_gates_users_by_id() {
+    let inline_allowed = [UserId(7)];
+    let chats_deny_everyone = |_: ChatId| false;
+    // The inline list alone decides, even for a user the chat lists would refuse
+    assert!(is_listed_inline_user_allowed(
+      UserId(7),
+      &inline_allowed,
+      chats_deny_everyone
+    ));
+    assert!(!is_listed_inline_user_allowed(
+      UserId(8),
+      &inline_allowed,
+      |_| true
+    ));
+  }
+
+  #[test]
+  fn unset_inline_allowlist_falls_back_to_the_chat_lists() {
+    let denied = [ChatId::from(UserId(8))];
+    let chat_lists = |chat_id: ChatId| is_listed_chat_allowed(chat_id, &[], &denied);
+    assert!(is_listed_inline_user_allowed(UserId(7), &[], chat_lists));
+    assert!(!is_listed_inline_user_allowed(UserId(8), &[], chat_lists));
+  }
+
+  #[test]
+  fn only_listed_senders_are_admins() {
+    let user = |id: u64| -> User {
+      serde_json::from_value(serde_json::json!({
+        "id": id,
+        "is_bot": false,
+        "first_name": "Op"
+      }))
+      .unwrap()
+    };
+    let admins = [UserId(7)];
+    assert!(is_listed_admin(Some(&user(7)), &admins));
+    assert!(!is_listed_admin(Some(&user(8)), &admins));
+    // Channel posts have no sender, and an unset list has no admins
+    assert!(!is_listed_admin(None, &admins));
+    assert!(!is_listed_admin(Some(&user(7)), &[]));
+  }
+}
@@ -0,0 +1,365 @@
+use log::{info, warn};
+use rand::seq::SliceRandom;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use teloxide::types::{ChatId, MessageId};
+
+use crate::config_file::setting;
+
+const DEFAULT_ALIASES_FILE: &str = "aliases.json";
+// Keeps one chat from growing the aliases file without bound
+pub const MAX_ALIASES_PER_CHAT: usize = 50;
+static CHAT_SETTINGS: OnceLock<Mutex<HashMap<ChatId, ChatSettings>>> = OnceLock::new();
+
+// Which order search candidates are tried in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ResultOrder {
+  // Engine ranking, best match first
+  Relevance,
+  // Uniformly random among all candidates, so repeated queries vary
+  Shuffle,
+}
+
+impl ResultOrder {
+  pub fn parse(value: &str) -> Option<Self> {
+    match value.trim().to_lowercase().as_str() {
+      "relevance" => Some(ResultOrder::Relevance),
+      "shuffle" => Some(ResultOrder::Shuffle),
+      _ => None,
+    }
+  }
+
+  pub fn label(self) -> &'static str {
+    match self {
+      ResultOrder::Relevance => "relevance",
+      ResultOrder::Shuffle => "shuffle",
+    }
+  }
+
+  // Reorder ranked candidates for sending
+  pub fn apply<T>(self, candidates: &mut [T]) {
+    if self == ResultOrder::Shuffle {
+      candidates.shuffle(&mut rand::thread_rng());
+    }
+  }
+}
+
+// Per-chat overrides set by commands; unset fields fall back to the env defaults.
+// Toggles are kept in memory only and reset when the bot restarts; aliases are saved to ALIASES_FILE.
+#[derive(Clone, Debug, Default)]
+struct ChatSettings {
+  reply: Option<bool>,
+  order: Option<ResultOrder>,
+  hashtags: Option<bool>,
+  query_caption: Option<bool>,
+  // Normalized query -> replacement query, managed by /alias and /unalias
+  aliases: HashMap<String, String>,
+}
+
+// Whether images in this chat are sent as replies to the triggering message.
+// /reply_on and /reply_off override REPLY_TO_TRIGGER.
+pub fn reply_enabled(chat_id: ChatId) -> bool {
+  if let Some(enabled) = chat_settings()
+    .get(&chat_id)
+    .and_then(|settings| settings.reply)
+  {
+    return enabled;
+  }
+
+  setting("REPLY_TO_TRIGGER")
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+pub fn set_reply_enabled(chat_id: ChatId, enabled: bool) {
+  chat_settings().entry(chat_id).or_default().reply = Some(enabled);
+}
+
+// The message to reply to, or None when replies are off in this chat
+pub fn reply_target(chat_id: ChatId, message_id: MessageId) -> Option<MessageId> {
+  reply_enabled(chat_id).then_some(message_id)
+}
+
+// Whether a #hashtag in this chat triggers a search without the ".jpg" suffix.
+// /hashtag_on and /hashtag_off override HASHTAG_TRIGGER.
+pub fn hashtags_enabled(chat_id: ChatId) -> bool {
+  if let Some(enabled) = chat_settings()
+    .get(&chat_id)
+    .and_then(|settings| settings.hashtags)
+  {
+    return enabled;
+  }
+
+  setting("HASHTAG_TRIGGER")
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+pub fn set_hashtags_enabled(chat_id: ChatId, enabled: bool) {
+  chat_settings().entry(chat_id).or_default().hashtags = Some(enabled);
+}
+
+// Whether images found online in this chat carry the search query as their caption.
+// /querycaption_on and /querycaption_off override QUERY_CAPTION.
+pub fn query_caption_enabled(chat_id: ChatId) -> bool {
+  if let Some(enabled) = chat_settings()
+    .get(&chat_id)
+    .and_then(|settings| settings.query_caption)
+  {
+    return enabled;
+  }
+
+  setting("QUERY_CAPTION")
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+pub fn set_query_caption_enabled(chat_id: ChatId, enabled: bool) {
+  chat_settings().entry(chat_id).or_default().query_caption = Some(enabled);
+}
+
+// Candidate order for this chat: /order overrides RESULT_ORDER (default relevance)
+pub fn result_order(chat_id: ChatId) -> ResultOrder {
+  if let Some(order) = chat_settings()
+    .get(&chat_id)
+    .and_then(|settings| settings.order)
+  {
+    return order;
+  }
+
+  setting("RESULT_ORDER")
+    .ok()
+    .and_then(|value| ResultOrder::parse(&value))
+    .unwrap_or(ResultOrder::Relevance)
+}
+
+pub fn set_result_order(chat_id: ChatId, order: ResultOrder) {
+  chat_settings().entry(chat_id).or_default().order = Some(order);
+}
+
+// Rewrite a query this chat has aliased to another one; anything else is returned unchanged
+pub fn resolve_alias(chat_id: ChatId, query: &str) -> String {
+  chat_settings()
+    .get(&chat_id)
+    .and_then(|settings| settings.aliases.get(&alias_key(query)))
+    .cloned()
+    .unwrap_or_else(|| query.to_string())
+}
+
+// Returns false when the chat already has MAX_ALIASES_PER_CHAT aliases and `from` is a new one
+pub fn set_alias(chat_id: ChatId, from: &str, to: &str) -> bool {
+  let mut settings = chat_settings();
+  let aliases = &mut settings.entry(chat_id).or_default().aliases;
+  let key = alias_key(from);
+  if aliases.len() >= MAX_ALIASES_PER_CHAT && !aliases.contains_key(&key) {
+    return false;
+  }
+  aliases.insert(key, to.trim().to_string());
+  true
+}
+
+// Returns whether an alias was actually removed
+pub fn remove_alias(chat_id: ChatId, from: &str) -> bool {
+  chat_settings()
+    .get_mut(&chat_id)
+    .is_some_and(|settings| settings.aliases.remove(&alias_key(from)).is_some())
+}
+
+// The chat's aliases as (from, to) pairs, sorted for display
+pub fn aliases(chat_id: ChatId) -> Vec<(String, String)> {
+  let mut aliases: Vec<_> = chat_settings()
+    .get(&chat_id)
+    .map(|settings| settings.aliases.clone().into_iter().collect())
+    .unwrap_or_default();
+  aliases.sort();
+  aliases
+}
+
+// Where aliases are kept across restarts, from ALIASES_FILE (default aliases.json)
+fn aliases_path() -> String {
+  setting("ALIASES_FILE").unwrap_or_else(|_| DEFAULT_ALIASES_FILE.to_string())
+}
+
+// Restore saved aliases at startup; a missing file just means none were set yet
+pub fn load_aliases() {
+  let path = aliases_path();
+  let Ok(contents) = fs::read_to_string(&path) else {
+    return;
+  };
+
+  match parse_aliases(&contents) {
+    Ok(saved) => {
+      let count: usize = saved.values().map(HashMap::len).sum();
+      let mut settings = chat_settings();
+      for (chat_id, aliases) in saved {
+        settings.entry(chat_id).or_default().aliases = aliases;
+      }
+      info!("Loaded {} alias(es) from {}", count, path);
+    }
+    Err(e) => warn!("Ignoring invalid aliases file {}: {}", path, e),
+  }
+}
+
+// Write every chat's aliases to ALIASES_FILE, after /alias or /unalias changed them.
+// The file is replaced by a rename so a crash mid-write can't leave it truncated.
+pub fn save_aliases() {
+  let path = aliases_path();
+  let contents = format_aliases(&chat_settings());
+  let temp_path = format!("{}.tmp", path);
+  if let Err(e) = fs::write(&temp_path, contents).and_then(|()| fs::rename(&temp_path, &path)) {
+    warn!("Failed to save aliases to {}: {}", path, e);
+  }
+}
+
+// {"<chat id>": {"<from>": "<to>", ...}, ...}
+fn parse_aliases(
+  contents: &str,
+) -> Result<HashMap<ChatId, HashMap<String, String>>, anyhow::Error> {
+  let parsed: Value = serde_json::from_str(contents)?;
+  let chats = parsed
+    .as_object()
+    .ok_or_else(|| anyhow::anyhow!("expected a JSON object of chat ID to aliases"))?;
+
+  chats
+    .iter()
+    .map(|(chat_id, aliases)| {
+      let aliases = aliases
+        .as_object()
+        .ok_or_else(|| anyhow::anyhow!("expected an object of aliases for chat {}", chat_id))?;
+      let aliases = aliases
+        .iter()
+        .filter_map(|(from, to)| Some((alias_key(from), to.as_str()?.to_string())))
+        .take(MAX_ALIASES_PER_CHAT)
+        .collect();
+      Ok((ChatId(chat_id.trim().parse()?), aliases))
+    })
+    .collect()
+}
+
+fn format_aliases(settings: &HashMap<ChatId, ChatSettings>) -> String {
+  let chats: serde_json::Map<String, Value> = settings
+    .iter()
+    .filter(|(_, settings)| !settings.aliases.is_empty())
+    .map(|(chat_id, settings)| (chat_id.to_string(), serde_json::json!(settings.aliases)))
+    .collect();
+  serde_json::to_string_pretty(&Value::Object(chats)).unwrap_or_default()
+}
+
+// Match aliases regardless of case and spacing, so "Mic  Drop" hits "mic drop"
+fn alias_key(query: &str) -> String {
+  query
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase()
+}
+
+fn chat_settings() -> MutexGuard<'static, HashMap<ChatId, ChatSettings>> {
+  CHAT_SETTINGS
+    .get_or_init(|| Mutex::new(HashMap::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reply_target_follows_the_chat_toggle() {
+    let chat_id = ChatId(668);
+    let message_id = MessageId(42);
+
+    set_reply_enabled(chat_id, true);
+    assert_eq!(reply_target(chat_id, message_id), Some(message_id));
+
+    set_reply_enabled(chat_id, false);
+    assert_eq!(reply_target(chat_id, message_id), None);
+  }
+
+  #[test]
+  fn relevance_keeps_the_ranking() {
+    let mut candidates: Vec<u32> = (0..20).collect();
+    ResultOrder::Relevance.apply(&mut candidates);
+    assert_eq!(candidates, (0..20).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn shuffle_reorders_among_all_candidates() {
+    let ranked: Vec<u32> = (0..20).collect();
+    let mut reordered = false;
+    for _ in 0..10 {
+      let mut candidates = ranked.clone();
+      ResultOrder::Shuffle.apply(&mut candidates);
+      reordered |= candidates != ranked;
+      candidates.sort();
+      assert_eq!(candidates, ranked);
+    }
+    assert!(reordered);
+  }
+
+  #[test]
+  fn order_is_set_per_chat() {
+    set_result_order(ChatId(677), ResultOrder::Shuffle);
+    assert_eq!(result_order(ChatId(677)), ResultOrder::Shuffle);
+    assert_eq!(
+      ResultOrder::parse(" Relevance "),
+      Some(ResultOrder::Relevance)
+    );
+  }
+
+  #[test]
+  fn alias_rewrites_queries_until_removed() {
+    let chat_id = ChatId(707);
+    set_alias(chat_id, "Mic  Drop", " obama mic drop ");
+
+    assert_eq!(resolve_alias(chat_id, "mic drop"), "obama mic drop");
+    assert_eq!(resolve_alias(ChatId(708), "mic drop"), "mic drop");
+    assert_eq!(
+      aliases(chat_id),
+      [("mic drop".to_string(), "obama mic drop".to_string())]
+    );
+
+    assert!(remove_alias(chat_id, "MIC DROP"));
+    assert!(!remove_alias(chat_id, "mic drop"));
+    assert_eq!(resolve_alias(chat_id, "mic drop"), "mic drop");
+  }
+
+  #[test]
+  fn aliases_are_capped_per_chat() {
+    let chat_id = ChatId(7071);
+    for n in 0..MAX_ALIASES_PER_CHAT {
+      assert!(set_alias(chat_id, &format!("from {}", n), "to"));
+    }
+
+    assert!(!set_alias(chat_id, "one more", "to"));
+    // Existing aliases can still be changed, and other chats are unaffected
+    assert!(set_alias(chat_id, "from 0", "elsewhere"));
+    assert_eq!(resolve_alias(chat_id, "from 0"), "elsewhere");
+    assert!(set_alias(ChatId(7072), "one more", "to"));
+  }
+
+  #[test]
+  fn saved_aliases_survive_a_round_trip() {
+    let mut settings = HashMap::new();
+    settings
+      .entry(ChatId(-1001))
+      .or_insert_with(ChatSettings::default)
+      .aliases = HashMap::from([("mic drop".to_string(), "obama mic drop".to_string())]);
+    // Chats with only toggles set aren't written
+    settings
+      .entry(ChatId(5))
+      .or_insert_with(ChatSettings::default)
+      .reply = Some(true);
+
+    let saved = format_aliases(&settings);
+    let loaded = parse_aliases(&saved).unwrap();
+
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(loaded[&ChatId(-1001)]["mic drop"], "obama mic drop");
+    assert!(parse_aliases(r#"{"not a chat": {}}"#).is_err());
+    assert!(parse_aliases("[]").is_err());
+  }
+}
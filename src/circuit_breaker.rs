@@ -0,0 +1,199 @@
+use log::{info, warn};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config_file::setting;
+
+const DEFAULT_COOLDOWN_SECS: u64 = 60;
+static BREAKER: OnceLock<Mutex<Circuit>> = OnceLock::new();
+
+// Closed: searching normally. Open: online search is paused after repeated failures.
+// HalfOpen: the cooldown passed and one probe search decides whether to close again.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Circuit {
+  Closed { failures: u32 },
+  Open { until: Instant },
+  HalfOpen { probe_started: Instant },
+}
+
+// Consecutive failed online searches that open the circuit, from SEND_FAILURE_THRESHOLD (0 disables)
+fn failure_threshold() -> u32 {
+  setting("SEND_FAILURE_THRESHOLD")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(0)
+}
+
+// How long online search stays paused once open, from CIRCUIT_COOLDOWN_SECS
+fn cooldown() -> Duration {
+  let secs = setting("CIRCUIT_COOLDOWN_SECS")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(DEFAULT_COOLDOWN_SECS);
+  Duration::from_secs(secs)
+}
+
+// Whether an online search may run now; after the cooldown the first caller becomes the probe
+pub fn allow_online() -> bool {
+  if failure_threshold() == 0 {
+    return true;
+  }
+
+  let mut circuit = circuit();
+  let was_open = !matches!(*circuit, Circuit::Closed { .. });
+  let allowed = circuit.admit(Instant::now(), cooldown());
+  if allowed && was_open {
+    info!("Online search cooldown over; probing with the next query");
+  }
+  allowed
+}
+
+// A successful send closes the circuit and resets the failure count
+pub fn record_online_success() {
+  if circuit().succeed() {
+    info!("Online search recovered; circuit closed");
+  }
+}
+
+// A search where nothing could be sent; enough of these in a row (or a failed probe) opens the circuit
+pub fn record_online_failure() {
+  let threshold = failure_threshold();
+  if threshold == 0 {
+    return;
+  }
+
+  let cooldown = cooldown();
+  if circuit().fail(Instant::now(), threshold, cooldown) {
+    warn!(
+      "{} online searches in a row failed to send; pausing online search for {}s",
+      threshold,
+      cooldown.as_secs()
+    );
+  }
+}
+
+impl Circuit {
+  fn admit(&mut self, now: Instant, cooldown: Duration) -> bool {
+    let blocked = match *self {
+      Circuit::Closed { .. } => return true,
+      Circuit::Open { until } => now < until,
+      // A probe that never reports back (e.g. cut off by a timeout) is replaced after a cooldown
+      Circuit::HalfOpen { probe_started } => now < probe_started + cooldown,
+    };
+    if !blocked {
+      *self = Circuit::HalfOpen { probe_started: now };
+    }
+    !blocked
+  }
+
+  // Returns true when this success closed a paused circuit
+  fn succeed(&mut self) -> bool {
+    let recovered = !matches!(*self, Circuit::Closed { .. });
+    *self = Circuit::Closed { failures: 0 };
+    recovered
+  }
+
+  // Returns true when this failure opened the circuit
+  fn fail(&mut self, now: Instant, threshold: u32, cooldown: Duration) -> bool {
+    let failures = match *self {
+      Circuit::Closed { failures } => failures + 1,
+      Circuit::HalfOpen { .. } => threshold,
+      // Searches that started before the circuit opened don't extend the cooldown
+      Circuit::Open { .. } => return false,
+    };
+
+    let opened = failures >= threshold;
+    *self = if opened {
+      Circuit::Open {
+        until: now + cooldown,
+      }
+    } else {
+      Circuit::Closed { failures }
+    };
+    opened
+  }
+}
+
+pub fn circuit_state() -> Circuit {
+  *circuit()
+}
+
+fn circuit() -> MutexGuard<'static, Circuit> {
+  BREAKER
+    .get_or_init(|| Mutex::new(Circuit::Closed { failures: 0 }))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn circuit_opens_probes_and_closes() {
+    let cooldown = Duration::from_secs(60);
+    let start = Instant::now();
+    let mut circuit = Circuit::Closed { failures: 0 };
+
+    assert!(!circuit.fail(start, 3, cooldown));
+    assert!(!circuit.fail(start, 3, cooldown));
+    assert_eq!(circuit, Circuit::Closed { failures: 2 });
+    assert!(circuit.fail(start, 3, cooldown));
+    assert_eq!(
+      circuit,
+      Circuit::Open {
+        until: start + cooldown
+      }
+    );
+
+    // Paused until the cooldown ends, then exactly one probe is let through
+    assert!(!circuit.admit(start + Duration::from_secs(59), cooldown));
+    let probe_at = start + cooldown;
+    assert!(circuit.admit(probe_at, cooldown));
+    assert_eq!(
+      circuit,
+      Circuit::HalfOpen {
+        probe_started: probe_at
+      }
+    );
+    assert!(!circuit.admit(probe_at + Duration::from_secs(1), cooldown));
+  }
+
+  #[test]
+  fn failed_probe_reopens_the_circuit() {
+    let cooldown = Duration::from_secs(60);
+    let probe_started = Instant::now();
+    let mut circuit = Circuit::HalfOpen { probe_started };
+
+    assert!(circuit.fail(probe_started, 5, cooldown));
+    assert_eq!(
+      circuit,
+      Circuit::Open {
+        until: probe_started + cooldown
+      }
+    );
+    // Late failures from searches started before the pause don't extend it
+    assert!(!circuit.fail(probe_started + Duration::from_secs(10), 5, cooldown));
+    assert_eq!(
+      circuit,
+      Circuit::Open {
+        until: probe_started + cooldown
+      }
+    );
+  }
+
+  #[test]
+  fn success_closes_a_half_open_circuit() {
+    let probe_started = Instant::now();
+    let mut circuit = Circuit::HalfOpen { probe_started };
+
+    assert!(circuit.succeed());
+
+    assert_eq!(circuit, Circuit::Closed { failures: 0 });
+    assert!(circuit.admit(probe_started, Duration::from_secs(60)));
+    // Successes while closed just keep the failure count at zero
+    let mut closed = Circuit::Closed { failures: 2 };
+    assert!(!closed.succeed());
+    assert_eq!(closed, Circuit::Closed { failures: 0 });
+  }
+}
@@ -0,0 +1,256 @@
+use anyhow::anyhow;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+const DEFAULT_CONFIG_PATH: &str = "config.json";
+// Settings read once at startup or on first use; changing them needs a restart
+const RESTART_REQUIRED: &[&str] = &[
+  "TELOXIDE_TOKEN",
+  "TELOXIDE_TOKENS",
+  "SERPER_API",
+  "SERP_API",
+  "SEARCH_ENGINES",
+  "GOOGLE_EXTRA_HEADERS",
+  "BLOCKED_URL_PATTERNS",
+  "PREFERRED_URL_PATTERNS",
+  "EXTRACT_LOG_PATH",
+  "DOWNLOAD_DOMAINS",
+  "ADMIN_USER_IDS",
+  "ALLOWED_CHATS",
+  "DENIED_CHATS",
+  "INLINE_ALLOWED_USERS",
+  "STOPWORDS",
+  "BANNED_QUERY_WORDS",
+  "FEATURED_FILE",
+  "ALIASES_FILE",
+  "DECODE_CONCURRENCY",
+  "REACTION_TRIGGER_EMOJI",
+  "HANDLE_CHANNEL_POSTS",
+  "SEND_METHODS",
+  "RUST_LOG",
+];
+static FILE_SETTINGS: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+
+// What a reload changed: settings now in effect, and changed ones that only apply after a restart
+#[derive(Debug, Default)]
+pub struct ConfigReload {
+  pub path: String,
+  pub applied: Vec<String>,
+  pub needs_restart: Vec<String>,
+}
+
+// A setting's current value: the environment variable if set, otherwise the config file's value
+// as of the last load or reload. Used in place of env::var for every setting.
+pub fn setting(key: &str) -> Result<String, env::VarError> {
+  match env::var(key) {
+    Err(env::VarError::NotPresent) => file_settings()
+      .get(key)
+      .cloned()
+      .ok_or(env::VarError::NotPresent),
+    result => result,
+  }
+}
+
+// Optional JSON file of settings (CONFIG_PATH, default config.json), e.g. {"SEND_DELAY_MS": 500}.
+// Real env vars win over the file's values. Returns the path and number of keys applied, or None
+// when no file was configured or found.
+pub fn load_config_file() -> Result<Option<(String, usize)>, anyhow::Error> {
+  let (path, explicit) = config_path();
+  let Some(entries) = read_config(&path, explicit)? else {
+    return Ok(None);
+  };
+
+  let applied = entries
+    .iter()
+    .filter(|(key, _)| env::var_os(key).is_none())
+    .count();
+  *file_settings_mut() = entries.into_iter().collect();
+  Ok(Some((path, applied)))
+}
+
+// Re-read the config file for /reloadconfig. Keys overridden by real env vars are ignored. Most
+// settings are read on every use and take effect immediately; those in RESTART_REQUIRED keep
+// their old value and are reported instead.
+pub fn reload_config_file() -> Result<ConfigReload, anyhow::Error> {
+  let (path, explicit) = config_path();
+  reload_from(path, explicit)
+}
+
+fn reload_from(path: String, explicit: bool) -> Result<ConfigReload, anyhow::Error> {
+  let entries = read_config(&path, explicit)?.unwrap_or_default();
+  let mut settings = file_settings_mut();
+  let (updated, applied, needs_restart) =
+    apply_reload(&settings, entries, |key| env::var_os(key).is_some());
+  *settings = updated;
+  Ok(ConfigReload {
+    path,
+    applied,
+    needs_restart,
+  })
+}
+
+// The file settings after a reload, with the changed keys that took effect and those that
+// need a restart. Keys set in the environment are left out of both lists.
+fn apply_reload(
+  current: &HashMap<String, String>,
+  entries: Vec<(String, String)>,
+  in_env: impl Fn(&str) -> bool,
+) -> (HashMap<String, String>, Vec<String>, Vec<String>) {
+  let mut updated: HashMap<String, String> = entries.into_iter().collect();
+  let mut changed: Vec<String> = current
+    .keys()
+    .chain(updated.keys())
+    .filter(|key| current.get(*key) != updated.get(*key) && !in_env(key))
+    .cloned()
+    .collect();
+  changed.sort();
+  changed.dedup();
+
+  let (needs_restart, applied): (Vec<String>, Vec<String>) = changed
+    .into_iter()
+    .partition(|key| RESTART_REQUIRED.contains(&key.as_str()));
+  for key in &needs_restart {
+    match current.get(key) {
+      Some(value) => updated.insert(key.clone(), value.clone()),
+      None => updated.remove(key),
+    };
+  }
+  (updated, applied, needs_restart)
+}
+
+fn config_path() -> (String, bool) {
+  match env::var("CONFIG_PATH") {
+    Ok(path) if !path.trim().is_empty() => (path.trim().to_string(), true),
+    _ => (DEFAULT_CONFIG_PATH.to_string(), false),
+  }
+}
+
+// The file's settings as (key, value) pairs; None when the default file doesn't exist
+fn read_config(path: &str, explicit: bool) -> Result<Option<Vec<(String, String)>>, anyhow::Error> {
+  let contents = match std::fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(_) if !explicit => return Ok(None),
+    Err(e) => return Err(anyhow!("could not read {}: {}", path, e)),
+  };
+
+  let parsed: Value = serde_json::from_str(&contents)?;
+  let entries = parsed
+    .as_object()
+    .ok_or_else(|| anyhow!("expected a JSON object of setting name to value"))?;
+
+  entries
+    .iter()
+    .map(|(key, value)| {
+      let value = config_value(value).ok_or_else(|| anyhow!("unsupported value for {}", key))?;
+      Ok((key.clone(), value))
+    })
+    .collect::<Result<Vec<_>, anyhow::Error>>()
+    .map(Some)
+}
+
+// Strings as-is, numbers and booleans as written, arrays as comma-separated lists and
+// objects (e.g. GOOGLE_EXTRA_HEADERS) as JSON
+fn config_value(value: &Value) -> Option<String> {
+  match value {
+    Value::String(value) => Some(value.clone()),
+    Value::Number(value) => Some(value.to_string()),
+    Value::Bool(value) => Some(value.to_string()),
+    Value::Array(values) => values
+      .iter()
+      .map(config_value)
+      .collect::<Option<Vec<_>>>()
+      .map(|values| values.join(",")),
+    Value::Object(_) => Some(value.to_string()),
+    Value::Null => None,
+  }
+}
+
+fn file_settings() -> RwLockReadGuard<'static, HashMap<String, String>> {
+  FILE_SETTINGS
+    .get_or_init(|| RwLock::new(HashMap::new()))
+    .read()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn file_settings_mut() -> RwLockWriteGuard<'static, HashMap<String, String>> {
+  FILE_SETTINGS
+    .get_or_init(|| RwLock::new(HashMap::new()))
+    .write()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::user_throttle::allow_search;
+  use teloxide::types::UserId;
+
+  // Puts back the file settings a test replaced by reloading its own file
+  struct RestoreSettings(HashMap<String, String>);
+
+  impl Drop for RestoreSettings {
+    fn drop(&mut self) {
+      *file_settings_mut() = std::mem::take(&mut self.0);
+    }
+  }
+
+  #[test]
+  fn env_overrides_the_file_value() {
+    let path = env::temp_dir().join(format!("tgjpg-config-{}.json", std::process::id()));
+    std::fs::write(
+      &path,
+      r#"{"OVERRIDDEN": "file", "FROM_FILE": 500, "LIST": ["a", "b"]}"#,
+    )
+    .unwrap();
+    let entries = read_config(&path.display().to_string(), true)
+      .unwrap()
+      .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut values = entries.clone();
+    values.sort();
+    assert_eq!(
+      values,
+      [
+        ("FROM_FILE".to_string(), "500".to_string()),
+        ("LIST".to_string(), "a,b".to_string()),
+        ("OVERRIDDEN".to_string(), "file".to_string()),
+      ]
+    );
+    let (_, applied, _) = apply_reload(&HashMap::new(), entries, |key| key == "OVERRIDDEN");
+    assert_eq!(applied, ["FROM_FILE", "LIST"]);
+  }
+
+  #[test]
+  fn changed_search_rate_takes_effect_after_reload() {
+    let _restore = RestoreSettings(file_settings().clone());
+    let path = env::temp_dir().join(format!("tgjpg-reload-{}.json", std::process::id()));
+    let user = UserId(728);
+
+    // As loaded at startup
+    *file_settings_mut() = HashMap::from([
+      ("USER_SEARCH_RATE".to_string(), "0".to_string()),
+      ("SEND_METHODS".to_string(), "a.com=document".to_string()),
+    ]);
+    for _ in 0..3 {
+      assert!(allow_search(user));
+    }
+
+    std::fs::write(
+      &path,
+      r#"{"USER_SEARCH_RATE": 1, "SEND_METHODS": "b.com=document"}"#,
+    )
+    .unwrap();
+    let reload = reload_from(path.display().to_string(), true).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(reload.applied, ["USER_SEARCH_RATE"]);
+    assert!(allow_search(user));
+    assert!(!allow_search(user));
+    // Read once at startup, so it is reported and keeps its old value
+    assert_eq!(reload.needs_restart, ["SEND_METHODS"]);
+    assert_eq!(setting("SEND_METHODS").unwrap(), "a.com=document");
+  }
+}
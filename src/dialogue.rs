@@ -0,0 +1,84 @@
+use anyhow::Result;
+use log::info;
+use teloxide::dispatching::dialogue::InMemStorage;
+use teloxide::prelude::*;
+
+use crate::bot_info::BotInfo;
+use crate::send_gate;
+
+// Per-chat conversation state for multi-step flows.
+//
+// To add a step: add a variant carrying whatever the step needs, move into it
+// with `dialogue.update(ChatState::NewStep { .. })`, and add a
+// `dptree::case![ChatState::NewStep { .. }]` branch in front of the message
+// endpoint in `main`. `/cancel` already resets any step back to `Idle`.
+#[derive(Clone, Debug, Default)]
+pub enum ChatState {
+  #[default]
+  Idle,
+}
+
+pub type ChatStorage = InMemStorage<ChatState>;
+pub type ChatDialogue = Dialogue<ChatState, ChatStorage>;
+
+pub fn new_storage() -> std::sync::Arc<ChatStorage> {
+  InMemStorage::new()
+}
+
+// Match "/cancel" and "/cancel@<our username>"; "/cancel@other_bot" belongs to another bot
+pub fn is_cancel_command(msg: Message, bot_info: BotInfo) -> bool {
+  msg
+    .text()
+    .is_some_and(|text| is_cancel_text(text, &bot_info))
+}
+
+fn is_cancel_text(text: &str, bot_info: &BotInfo) -> bool {
+  text
+    .split_whitespace()
+    .next()
+    .and_then(|command| bot_info.strip_command_suffix(command))
+    == Some("/cancel")
+}
+
+// Reset the chat back to Idle, dropping any in-progress flow
+pub async fn handle_cancel(
+  bot: Bot,
+  dialogue: ChatDialogue,
+  msg: Message,
+) -> Result<(), anyhow::Error> {
+  let reply = match dialogue.get().await?.unwrap_or_default() {
+    ChatState::Idle => "Nothing to cancel.",
+  };
+
+  info!("Cancelling dialogue in chat {}", msg.chat.id);
+  dialogue.exit().await?;
+  send_gate::acquire().await;
+  bot.send_message(msg.chat.id, reply).await?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn bot_info() -> BotInfo {
+    BotInfo {
+      username: "tgjpg_bot".to_string(),
+    }
+  }
+
+  #[test]
+  fn cancel_for_this_bot_matches() {
+    assert!(is_cancel_text("/cancel", &bot_info()));
+    assert!(is_cancel_text("/cancel@tgjpg_bot", &bot_info()));
+    assert!(is_cancel_text("/cancel@TgJpg_Bot now", &bot_info()));
+  }
+
+  #[test]
+  fn cancel_for_another_bot_is_ignored() {
+    assert!(!is_cancel_text("/cancel@otherbot", &bot_info()));
+    assert!(!is_cancel_text("/cancelled", &bot_info()));
+    assert!(!is_cancel_text("cancel", &bot_info()));
+  }
+}
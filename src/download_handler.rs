@@ -0,0 +1,308 @@
+use anyhow::Result;
+use log::{debug, warn};
+use reqwest::Client;
+use std::collections::VecDeque;
+use std::error::Error as _;
+use std::io;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::config_file::setting;
+
+const DEFAULT_DOWNLOAD_DOMAINS: &[&str] = &["imgur.com"];
+const DEFAULT_DOWNLOAD_CACHE_TTL_SECS: u64 = 300;
+const DOWNLOAD_CACHE_CAPACITY: usize = 32;
+// Anything smaller is a truncated or placeholder response, not a picture worth sending
+const MIN_IMAGE_BYTES: usize = 256;
+// Pause before the single retry after a connection reset or DNS failure
+const TRANSIENT_RETRY_DELAY: Duration = Duration::from_millis(500);
+static DOWNLOAD_DOMAINS: OnceLock<Vec<String>> = OnceLock::new();
+static DOWNLOAD_CACHE: OnceLock<Mutex<VecDeque<CachedDownload>>> = OnceLock::new();
+
+// Bytes fetched for a URL, kept briefly so repeated queries skip the network
+struct CachedDownload {
+  url: String,
+  fetched_at: Instant,
+  data: Vec<u8>,
+}
+
+// Hosts whose images are downloaded and sent as memory instead of hotlinked.
+// Telegram's URL fetch is rejected by these, so DOWNLOAD_DOMAINS (comma-separated) extends the imgur default.
+pub fn download_domains() -> &'static [String] {
+  DOWNLOAD_DOMAINS.get_or_init(|| {
+    let mut domains: Vec<String> = DEFAULT_DOWNLOAD_DOMAINS
+      .iter()
+      .map(|domain| domain.to_string())
+      .collect();
+
+    if let Ok(value) = setting("DOWNLOAD_DOMAINS") {
+      for domain in value.split(',') {
+        let domain = domain.trim().trim_start_matches('.').to_lowercase();
+        if !domain.is_empty() && !domains.contains(&domain) {
+          domains.push(domain);
+        }
+      }
+    }
+
+    domains
+  })
+}
+
+// Check if the URL's host is one of the domains (or a subdomain of one)
+pub fn should_download(url: &str, domains: &[String]) -> bool {
+  let host = match Url::parse(url) {
+    Ok(parsed) => match parsed.host_str() {
+      Some(host) => host.to_lowercase(),
+      None => return false,
+    },
+    Err(_) => {
+      warn!("Failed to parse URL for download check: {}", url);
+      return false;
+    }
+  };
+
+  domains
+    .iter()
+    .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+// Download image data from URL, reusing bytes fetched within DOWNLOAD_CACHE_TTL_SECS
+pub async fn download_image(url: &str) -> Result<Vec<u8>, anyhow::Error> {
+  let ttl = download_cache_ttl();
+  if let Some(data) = cached_download(url, ttl) {
+    debug!("Using cached download for {}", url);
+    return Ok(data);
+  }
+
+  let data = fetch_with_retry(&Client::new(), url).await?;
+  if !ttl.is_zero() {
+    cache_download(url, &data);
+  }
+  Ok(data)
+}
+
+async fn fetch_with_retry(client: &Client, url: &str) -> Result<Vec<u8>, anyhow::Error> {
+  match fetch_image(client, url).await {
+    Err(e) if is_transient(&e) => {
+      warn!(
+        "Transient error downloading {}, retrying once: {:#}",
+        url, e
+      );
+      tokio::time::sleep(TRANSIENT_RETRY_DELAY).await;
+      fetch_image(client, url).await
+    }
+    result => result,
+  }
+}
+
+async fn fetch_image(client: &Client, url: &str) -> Result<Vec<u8>, anyhow::Error> {
+  let response = client
+    .get(url)
+    .header(
+      "User-Agent",
+      "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36",
+    )
+    .send()
+    .await?;
+
+  if !response.status().is_success() {
+    return Err(anyhow::anyhow!("HTTP error: {}", response.status()));
+  }
+
+  let data = response.bytes().await?.to_vec();
+  if data.len() < MIN_IMAGE_BYTES {
+    return Err(anyhow::anyhow!(
+      "response too small to be an image: {} bytes",
+      data.len()
+    ));
+  }
+  Ok(data)
+}
+
+// DNS failures, refused connections and resets mid-transfer are worth one more try;
+// HTTP status errors and timeouts are not
+fn is_transient(error: &anyhow::Error) -> bool {
+  let Some(error) = error.downcast_ref::<reqwest::Error>() else {
+    return false;
+  };
+  if error.is_connect() {
+    return true;
+  }
+
+  let mut source = error.source();
+  while let Some(cause) = source {
+    if let Some(io_error) = cause.downcast_ref::<io::Error>() {
+      return matches!(
+        io_error.kind(),
+        io::ErrorKind::ConnectionReset
+          | io::ErrorKind::ConnectionAborted
+          | io::ErrorKind::BrokenPipe
+          | io::ErrorKind::UnexpectedEof
+      );
+    }
+    source = cause.source();
+  }
+  false
+}
+
+// 0 disables the cache
+fn download_cache_ttl() -> Duration {
+  let secs = setting("DOWNLOAD_CACHE_TTL_SECS")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(DEFAULT_DOWNLOAD_CACHE_TTL_SECS);
+  Duration::from_secs(secs)
+}
+
+fn cached_download(url: &str, ttl: Duration) -> Option<Vec<u8>> {
+  let mut cache = download_cache();
+  cache.retain(|cached| cached.fetched_at.elapsed() < ttl);
+  cache
+    .iter()
+    .find(|cached| cached.url == url)
+    .map(|cached| cached.data.clone())
+}
+
+fn cache_download(url: &str, data: &[u8]) {
+  let mut cache = download_cache();
+  cache.retain(|cached| cached.url != url);
+  if cache.len() >= DOWNLOAD_CACHE_CAPACITY {
+    cache.pop_front();
+  }
+  cache.push_back(CachedDownload {
+    url: url.to_string(),
+    fetched_at: Instant::now(),
+    data: data.to_vec(),
+  });
+}
+
+// Drop every cached download, returning how many entries were removed
+pub fn clear_download_cache() -> usize {
+  let mut cache = download_cache();
+  let cleared = cache.len();
+  cache.clear();
+  cleared
+}
+
+fn download_cache() -> MutexGuard<'static, VecDeque<CachedDownload>> {
+  DOWNLOAD_CACHE
+    .get_or_init(|| Mutex::new(VecDeque::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn domains() -> Vec<String> {
+    vec!["imgur.com".to_string(), "example.org".to_string()]
+  }
+
+  #[test]
+  fn listed_domain_is_downloaded() {
+    assert!(should_download("https://imgur.com/a.jpg", &domains()));
+    assert!(should_download("https://EXAMPLE.org/b.png", &domains()));
+  }
+
+  #[test]
+  fn subdomain_is_downloaded() {
+    assert!(should_download("https://i.imgur.com/a.jpg", &domains()));
+  }
+
+  #[test]
+  fn other_hosts_are_hotlinked() {
+    assert!(!should_download("https://notimgur.com/a.jpg", &domains()));
+    assert!(!should_download(
+      "https://imgur.com.evil.net/a.jpg",
+      &domains()
+    ));
+    assert!(!should_download("not a url", &domains()));
+  }
+
+  #[test]
+  fn cached_download_is_reused_within_ttl() {
+    let url = "https://i.imgur.com/cached-download-test.jpg";
+    let ttl = Duration::from_secs(300);
+    assert!(cached_download(url, ttl).is_none());
+
+    cache_download(url, &[1, 2, 3]);
+
+    // A hit means download_image returns without touching the network
+    assert_eq!(cached_download(url, ttl), Some(vec![1, 2, 3]));
+    assert!(cached_download(url, Duration::ZERO).is_none());
+  }
+
+  // Answer a single request with a 200 carrying `body`, returning the URL to fetch
+  fn serve_once(body: Vec<u8>) -> String {
+    serve_after_resets(0, body)
+  }
+
+  // Like serve_once, but the first `resets` connections are reset instead of answered
+  fn serve_after_resets(resets: usize, body: Vec<u8>) -> String {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+      for _ in 0..resets {
+        let (stream, _) = listener.accept().unwrap();
+        // Closing with the request still unread makes the kernel send a reset
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(stream);
+      }
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut request = [0; 4096];
+      let _ = stream.read(&mut request);
+      let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+      );
+      stream.write_all(head.as_bytes()).unwrap();
+      stream.write_all(&body).unwrap();
+    });
+    format!("http://{}/image.jpg", address)
+  }
+
+  fn local_client() -> Client {
+    Client::builder().no_proxy().build().unwrap()
+  }
+
+  #[tokio::test]
+  async fn empty_body_is_an_error() {
+    let url = serve_once(Vec::new());
+
+    let error = fetch_image(&local_client(), &url).await.unwrap_err();
+
+    assert!(error.to_string().contains("0 bytes"));
+    assert!(!is_transient(&error));
+  }
+
+  #[tokio::test]
+  async fn full_sized_body_is_returned() {
+    let url = serve_once(vec![0xAB; MIN_IMAGE_BYTES]);
+
+    let data = fetch_image(&local_client(), &url).await.unwrap();
+
+    assert_eq!(data.len(), MIN_IMAGE_BYTES);
+  }
+
+  #[tokio::test]
+  async fn connection_reset_is_retried_once() {
+    let url = serve_after_resets(1, vec![0xAB; MIN_IMAGE_BYTES]);
+
+    let data = fetch_with_retry(&local_client(), &url).await.unwrap();
+
+    assert_eq!(data.len(), MIN_IMAGE_BYTES);
+  }
+
+  #[tokio::test]
+  async fn second_reset_gives_up() {
+    let url = serve_after_resets(2, vec![0xAB; MIN_IMAGE_BYTES]);
+
+    let error = fetch_with_retry(&local_client(), &url).await.unwrap_err();
+
+    assert!(is_transient(&error));
+  }
+}
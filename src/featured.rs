@@ -0,0 +1,140 @@
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config_file::setting;
+
+const DEFAULT_FEATURED_FILE: &str = "featured.json";
+static FEATURED: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+// Keyword -> image URL pins that always win over search results.
+// Loaded once from FEATURED_FILE (default featured.json), e.g. {"mic drop": "https://..."}.
+pub fn featured() -> &'static HashMap<String, String> {
+  FEATURED.get_or_init(|| {
+    let path = setting("FEATURED_FILE").unwrap_or_else(|_| DEFAULT_FEATURED_FILE.to_string());
+    let contents = match std::fs::read_to_string(&path) {
+      Ok(contents) => contents,
+      Err(_) => return HashMap::new(),
+    };
+
+    match parse_featured(&contents) {
+      Ok(featured) => {
+        info!("Loaded {} featured keywords from {}", featured.len(), path);
+        featured
+      }
+      Err(e) => {
+        warn!("Ignoring invalid featured file {}: {}", path, e);
+        HashMap::new()
+      }
+    }
+  })
+}
+
+pub fn parse_featured(contents: &str) -> Result<HashMap<String, String>, anyhow::Error> {
+  let parsed: Value = serde_json::from_str(contents)?;
+  let entries = parsed
+    .as_object()
+    .ok_or_else(|| anyhow::anyhow!("expected a JSON object of keyword to URL"))?;
+
+  Ok(
+    entries
+      .iter()
+      .filter_map(|(keyword, url)| Some((normalize_keyword(keyword), url.as_str()?.to_string())))
+      .collect(),
+  )
+}
+
+// Look up the pinned URL for a query, ignoring case, whitespace, accents and hiragana/katakana
+pub fn find_featured<'a>(query: &str, featured: &'a HashMap<String, String>) -> Option<&'a str> {
+  featured.get(&normalize_keyword(query)).map(String::as_str)
+}
+
+// Put a pinned URL ahead of the ranked results, dropping its duplicate further down
+pub fn pin_first(urls: &mut Vec<String>, pinned: &str) {
+  urls.retain(|url| url != pinned);
+  urls.insert(0, pinned.to_string());
+}
+
+pub(crate) fn normalize_keyword(keyword: &str) -> String {
+  keyword
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase()
+    .nfd()
+    .filter(|c| !is_latin_diacritic(*c))
+    .nfc()
+    .map(fold_kana)
+    .collect()
+}
+
+// Combining accents on Latin letters, dropped so "café" pins the same keyword as "cafe".
+// Only this block is removed: kana voicing marks decompose too and must survive.
+fn is_latin_diacritic(c: char) -> bool {
+  ('\u{0300}'..='\u{036F}').contains(&c)
+}
+
+// Map hiragana to katakana so "あのん" and "アノン" pin the same keyword
+fn fold_kana(c: char) -> char {
+  match c {
+    '\u{3041}'..='\u{3096}' | '\u{309D}'..='\u{309E}' => {
+      char::from_u32(c as u32 + 0x60).unwrap_or(c)
+    }
+    _ => c,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn hiragana_and_katakana_share_a_key() {
+    assert_eq!(fold_kana('あ'), 'ア');
+    assert_eq!(normalize_keyword("あのん"), normalize_keyword("アノン"));
+    // Voiced kana survive accent stripping
+    assert_eq!(normalize_keyword("がっこう"), normalize_keyword("ガッコウ"));
+  }
+
+  #[test]
+  fn pinned_katakana_keyword_matches_hiragana_query() {
+    let featured = parse_featured(r#"{"アノン": "https://example.com/anon.gif"}"#).unwrap();
+    assert_eq!(
+      find_featured("あのん", &featured),
+      Some("https://example.com/anon.gif")
+    );
+  }
+
+  #[test]
+  fn featured_keyword_beats_the_top_ranked_result() {
+    let featured = parse_featured(r#"{"Mic Drop": "https://example.com/pinned.gif"}"#).unwrap();
+    let mut urls = vec![
+      "https://example.com/best-match.gif".to_string(),
+      "https://example.com/pinned.gif".to_string(),
+    ];
+
+    pin_first(&mut urls, find_featured("mic  drop", &featured).unwrap());
+
+    assert_eq!(
+      urls,
+      [
+        "https://example.com/pinned.gif",
+        "https://example.com/best-match.gif"
+      ]
+    );
+  }
+
+  #[test]
+  fn accented_query_matches_unaccented_keyword() {
+    let featured = parse_featured(r#"{"cafe": "https://example.com/cafe.jpg"}"#).unwrap();
+    assert_eq!(
+      find_featured("Café", &featured),
+      Some("https://example.com/cafe.jpg")
+    );
+    // Decomposed input ("e" + combining acute) folds the same way
+    assert_eq!(normalize_keyword("cafe\u{301}"), "cafe");
+    assert_eq!(normalize_keyword("crème brûlée"), "creme brulee");
+  }
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use teloxide::prelude::*;
+
+use crate::config_file::setting;
+use crate::send_gate;
+
+const FEEDBACK_COOLDOWN: Duration = Duration::from_secs(60);
+static LAST_FEEDBACK: OnceLock<Mutex<HashMap<UserId, Instant>>> = OnceLock::new();
+
+// Read the admin chat that receives feedback, if configured
+pub fn feedback_chat_id() -> Option<ChatId> {
+  setting("FEEDBACK_CHAT_ID")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .map(ChatId)
+}
+
+// Relay "/feedback <text>" to FEEDBACK_CHAT_ID; silently does nothing when unset
+pub async fn forward_feedback(bot: &Bot, msg: &Message, text: &str) -> Result<(), anyhow::Error> {
+  let Some(admin_chat) = feedback_chat_id() else {
+    return Ok(());
+  };
+
+  if text.is_empty() {
+    send_gate::acquire().await;
+    bot
+      .send_message(msg.chat.id, "Usage: /feedback <your message>")
+      .await?;
+    return Ok(());
+  }
+
+  if let Some(user) = msg.from.as_ref() {
+    if !try_acquire_cooldown(user.id) {
+      send_gate::acquire().await;
+      bot
+        .send_message(
+          msg.chat.id,
+          "Please wait a minute before sending more feedback.",
+        )
+        .await?;
+      return Ok(());
+    }
+  }
+
+  info!("Forwarding feedback from chat {}", msg.chat.id);
+  send_gate::acquire().await;
+  bot
+    .send_message(admin_chat, format_feedback(msg, text))
+    .await?;
+  send_gate::acquire().await;
+  bot
+    .send_message(msg.chat.id, "Thanks! Your feedback has been sent.")
+    .await?;
+
+  Ok(())
+}
+
+// Build the message relayed to the admin chat, including chat and user context
+pub fn format_feedback(msg: &Message, text: &str) -> String {
+  let chat = match msg.chat.title().or(msg.chat.username()) {
+    Some(name) => format!("{} ({})", name, msg.chat.id),
+    None => msg.chat.id.to_string(),
+  };
+  let user = match msg.from.as_ref() {
+    Some(user) => match user.username.as_deref() {
+      Some(username) => format!("{} (@{}, {})", user.full_name(), username, user.id),
+      None => format!("{} ({})", user.full_name(), user.id),
+    },
+    None => "unknown".to_string(),
+  };
+
+  format!("Feedback\nChat: {}\nFrom: {}\n\n{}", chat, user, text)
+}
+
+fn try_acquire_cooldown(user_id: UserId) -> bool {
+  let mut last_feedback = LAST_FEEDBACK
+    .get_or_init(|| Mutex::new(HashMap::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner());
+  let now = Instant::now();
+
+  match last_feedback.get(&user_id) {
+    Some(last) if now.duration_since(*last) < FEEDBACK_COOLDOWN => {
+      warn!("Rate-limited feedback from user {}", user_id);
+      false
+    }
+    _ => {
+      last_feedback.insert(user_id, now);
+      true
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn feedback_includes_user_chat_and_text() {
+    let msg: Message = serde_json::from_value(serde_json::json!({
+      "message_id": 1,
+      "date": 0,
+      "chat": { "id": -100123, "type": "supergroup", "title": "Cats" },
+      "from": { "id": 42, "is_bot": false, "first_name": "Ada", "username": "ada" },
+      "text": "/feedback more cats please"
+    }))
+    .unwrap();
+
+    let feedback = format_feedback(&msg, "more cats please");
+
+    assert!(feedback.contains("@ada"));
+    assert!(feedback.contains("Cats (-100123)"));
+    assert!(feedback.ends_with("\n\nmore cats please"));
+  }
+}
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use teloxide::types::Message;
+use teloxide::{ApiError, RequestError};
+
+use crate::send_method::SendMethod;
+
+const FILE_ID_CACHE_CAPACITY: usize = 500;
+static FILE_IDS: OnceLock<Mutex<FileIdCache>> = OnceLock::new();
+
+// A file Telegram already holds for an image URL. File IDs are only valid for the bot that
+// received them, and a requested animation may have gone out as a document instead.
+struct CachedFile {
+  bot_id: String,
+  url: String,
+  requested: SendMethod,
+  sent: SendMethod,
+  file_id: String,
+}
+
+impl CachedFile {
+  fn matches(&self, bot_id: &str, url: &str, requested: SendMethod) -> bool {
+    self.bot_id == bot_id && self.url == url && self.requested == requested
+  }
+}
+
+// Least recently used first; a hit moves the entry to the back so hot URLs survive eviction
+struct FileIdCache {
+  files: VecDeque<CachedFile>,
+  capacity: usize,
+}
+
+impl FileIdCache {
+  fn new(capacity: usize) -> Self {
+    FileIdCache {
+      files: VecDeque::new(),
+      capacity,
+    }
+  }
+
+  fn get(
+    &mut self,
+    bot_id: &str,
+    url: &str,
+    requested: SendMethod,
+  ) -> Option<(SendMethod, String)> {
+    let index = self
+      .files
+      .iter()
+      .position(|file| file.matches(bot_id, url, requested))?;
+    let file = self.files.remove(index)?;
+    let found = (file.sent, file.file_id.clone());
+    self.files.push_back(file);
+    Some(found)
+  }
+
+  fn insert(&mut self, file: CachedFile) {
+    self
+      .files
+      .retain(|cached| !cached.matches(&file.bot_id, &file.url, file.requested));
+    if self.files.len() >= self.capacity {
+      self.files.pop_front();
+    }
+    self.files.push_back(file);
+  }
+
+  fn forget(&mut self, bot_id: &str, url: &str) {
+    self
+      .files
+      .retain(|file| !(file.bot_id == bot_id && file.url == url));
+  }
+}
+
+// The file ID and the method it was sent with, if this bot sent the URL before in any chat
+pub fn cached_file_id(
+  bot_id: &str,
+  url: &str,
+  requested: SendMethod,
+) -> Option<(SendMethod, String)> {
+  file_ids().get(bot_id, url, requested)
+}
+
+pub fn remember_file_id(
+  bot_id: &str,
+  url: &str,
+  requested: SendMethod,
+  sent: SendMethod,
+  file_id: String,
+) {
+  file_ids().insert(CachedFile {
+    bot_id: bot_id.to_string(),
+    url: url.to_string(),
+    requested,
+    sent,
+    file_id,
+  });
+}
+
+pub fn forget_file_id(bot_id: &str, url: &str) {
+  file_ids().forget(bot_id, url);
+}
+
+// The file ID of the media in a message the bot just sent (the largest size for photos)
+pub fn sent_file_id(message: &Message, sent: SendMethod) -> Option<String> {
+  let file = match sent {
+    SendMethod::Photo => message
+      .photo()?
+      .iter()
+      .max_by_key(|size| size.width * size.height)?
+      .file
+      .clone(),
+    SendMethod::Animation => message.animation()?.file.clone(),
+    SendMethod::Document => message.document()?.file.clone(),
+    SendMethod::Sticker => message.sticker()?.file.clone(),
+  };
+  Some(file.id)
+}
+
+// Telegram no longer accepts a cached file ID, so it should be dropped and the URL sent again
+pub fn is_stale_file_id(error: &anyhow::Error) -> bool {
+  matches!(
+    error.downcast_ref::<RequestError>(),
+    Some(RequestError::Api(
+      ApiError::WrongFileId | ApiError::WrongFileIdOrUrl
+    ))
+  )
+}
+
+fn file_ids() -> MutexGuard<'static, FileIdCache> {
+  FILE_IDS
+    .get_or_init(|| Mutex::new(FileIdCache::new(FILE_ID_CACHE_CAPACITY)))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cached(url: &str) -> CachedFile {
+    CachedFile {
+      bot_id: "1".to_string(),
+      url: url.to_string(),
+      requested: SendMethod::Photo,
+      sent: SendMethod::Photo,
+      file_id: format!("id-{}", url),
+    }
+  }
+
+  #[test]
+  fn least_recently_used_entry_is_evicted() {
+    let capacity = 3;
+    let mut cache = FileIdCache::new(capacity);
+    for index in 0..capacity {
+      cache.insert(cached(&index.to_string()));
+    }
+    // Touch the oldest entry so "1" becomes the least recently used
+    assert!(cache.get("1", "0", SendMethod::Photo).is_some());
+
+    cache.insert(cached("new"));
+
+    assert!(cache.get("1", "0", SendMethod::Photo).is_some());
+    assert!(cache.get("1", "1", SendMethod::Photo).is_none());
+    assert!(cache.get("1", "2", SendMethod::Photo).is_some());
+    assert!(cache.get("1", "new", SendMethod::Photo).is_some());
+  }
+
+  #[test]
+  fn second_lookup_hits_and_other_methods_miss() {
+    let mut cache = FileIdCache::new(10);
+    cache.insert(cached("cat.jpg"));
+
+    let hit = cache.get("1", "cat.jpg", SendMethod::Photo);
+    assert_eq!(hit, Some((SendMethod::Photo, "id-cat.jpg".to_string())));
+    assert_eq!(
+      cache.get("1", "cat.jpg", SendMethod::Photo),
+      Some((SendMethod::Photo, "id-cat.jpg".to_string()))
+    );
+    assert!(cache.get("1", "cat.jpg", SendMethod::Document).is_none());
+    assert!(cache.get("2", "cat.jpg", SendMethod::Photo).is_none());
+  }
+}
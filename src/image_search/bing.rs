@@ -1,12 +1,17 @@
 use log::{debug, error, info, warn};
+use reqwest::header::CONTENT_TYPE;
 use reqwest::Client;
 use reqwest::StatusCode;
 use std::collections::HashSet;
 
-use crate::image_search::{SearchError, MAX_RESULTS};
+use crate::image_search::{decode_html, extract_limit, SearchError, SearchLocale};
 
 // Search for images using Bing Image Search
-pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchError> {
+pub async fn search(
+  query: &str,
+  is_gif: bool,
+  locale: &SearchLocale,
+) -> Result<Vec<String>, SearchError> {
   let endpoint = "https://www.bing.com/images/search";
 
   // filterui:photo-animatedgif for GIF, filterui:photo-photo for static images
@@ -40,7 +45,7 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
       "Accept",
       "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8",
     )
-    .header("Accept-Language", "zh-TW,zh;q=0.9,en-US;q=0.8,en;q=0.7")
+    .header("Accept-Language", locale.accept_language())
     .header("Referer", "https://www.bing.com/")
     .send()
     .await
@@ -52,6 +57,11 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
   let status = res.status();
   info!("Received response with status: {}", status);
 
+  let content_type = res
+    .headers()
+    .get(CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .map(str::to_string);
   let bytes = res
     .bytes()
     .await
@@ -61,7 +71,7 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
     })?;
   info!("HTML response length: {} bytes", bytes.len());
 
-  let html = String::from_utf8_lossy(&bytes);
+  let html = decode_html("Bing", content_type.as_deref(), &bytes)?;
 
   debug!(
     "HTML snippet: {}",
@@ -101,7 +111,7 @@ fn extract_image_urls(text: &str) -> Vec<String> {
   let iusc_regex = regex::Regex::new(r#"<a[^>]+class="iusc"[^>]+m="(\{[^"]+\})"[^>]*>"#).unwrap();
 
   for cap in iusc_regex.captures_iter(text) {
-    if urls.len() >= MAX_RESULTS {
+    if urls.len() >= extract_limit() {
       break;
     }
     if let Some(m_match) = cap.get(1) {
@@ -123,7 +133,7 @@ fn extract_image_urls(text: &str) -> Vec<String> {
     let murl_regex = regex::Regex::new(r#""murl"\s*:\s*"(https?://[^"]+)""#).unwrap();
 
     for cap in murl_regex.captures_iter(text) {
-      if urls.len() >= MAX_RESULTS {
+      if urls.len() >= extract_limit() {
         break;
       }
       if let Some(url_match) = cap.get(1) {
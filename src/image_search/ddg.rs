@@ -4,14 +4,18 @@ use reqwest::StatusCode;
 use serde_json::Value;
 use std::collections::HashSet;
 
-use crate::image_search::{SearchError, MAX_RESULTS};
+use crate::image_search::{extract_limit, is_unwanted_size, SearchError, SearchLocale};
 
 // Search for images using DuckDuckGo Image Search
 //
 // DDG image search requires two requests:
 //   1. GET duckduckgo.com/?q=...&iax=images&ia=images  → extract vqd token from HTML
 //   2. GET duckduckgo.com/i.js?q=...&vqd=TOKEN&o=json  → returns JSON with image URLs
-pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchError> {
+pub async fn search(
+  query: &str,
+  is_gif: bool,
+  locale: &SearchLocale,
+) -> Result<Vec<String>, SearchError> {
   info!(
     "Searching DuckDuckGo Images for query: '{}', is_gif: {}",
     query, is_gif
@@ -26,7 +30,7 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
     })?;
 
   // Step 1: fetch the search page to obtain the vqd token
-  let vqd = fetch_vqd(&client, query).await?;
+  let vqd = fetch_vqd(&client, query, locale).await?;
   info!("Got vqd token: {}", vqd);
 
   // Step 2: fetch image results using the vqd token
@@ -49,7 +53,7 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
       "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36",
     )
     .header("Accept", "application/json, text/javascript, */*; q=0.01")
-    .header("Accept-Language", "zh-TW,zh;q=0.9,en-US;q=0.8,en;q=0.7")
+    .header("Accept-Language", locale.accept_language())
     .header("Referer", "https://duckduckgo.com/")
     .header("X-Requested-With", "XMLHttpRequest")
     .send()
@@ -92,7 +96,11 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
 
 // Step 1: load the DDG search page and pull out the vqd token
 // The token appears as: vqd="3-..." or vqd='3-...' somewhere in the HTML/JS
-async fn fetch_vqd(client: &Client, query: &str) -> Result<String, SearchError> {
+async fn fetch_vqd(
+  client: &Client,
+  query: &str,
+  locale: &SearchLocale,
+) -> Result<String, SearchError> {
   let params = [("q", query), ("iax", "images"), ("ia", "images")];
 
   let res = client
@@ -106,7 +114,7 @@ async fn fetch_vqd(client: &Client, query: &str) -> Result<String, SearchError>
       "Accept",
       "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8",
     )
-    .header("Accept-Language", "zh-TW,zh;q=0.9,en-US;q=0.8,en;q=0.7")
+    .header("Accept-Language", locale.accept_language())
     .send()
     .await
     .map_err(|err| SearchError::NetworkFailed {
@@ -167,10 +175,17 @@ fn extract_image_urls(json: &str) -> Result<Vec<String>, SearchError> {
 
   if let Some(results) = parsed.get("results").and_then(Value::as_array) {
     for result in results {
-      if urls.len() >= MAX_RESULTS {
+      if urls.len() >= extract_limit() {
         break;
       }
 
+      if is_unwanted_size(
+        result.get("width").and_then(Value::as_u64),
+        result.get("height").and_then(Value::as_u64),
+      ) {
+        continue;
+      }
+
       if let Some(url) = result.get("image").and_then(Value::as_str) {
         let url = url.replace("\\u0026", "&").replace("\\u003d", "=");
         if seen.insert(url.clone()) {
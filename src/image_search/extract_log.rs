@@ -0,0 +1,91 @@
+use log::warn;
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config_file::setting;
+
+static LOG_PATH: OnceLock<Option<String>> = OnceLock::new();
+static LOG_LOCK: Mutex<()> = Mutex::new(());
+
+// Optional JSONL file (EXTRACT_LOG_PATH) collecting what each engine extracted, for offline analysis
+fn log_path() -> Option<&'static str> {
+  LOG_PATH
+    .get_or_init(|| {
+      setting("EXTRACT_LOG_PATH")
+        .ok()
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty())
+    })
+    .as_deref()
+}
+
+// Append one line per engine result: {"ts", "engine", "query", "is_gif", "urls"}
+pub fn log_extracted(engine: &str, query: &str, is_gif: bool, urls: &[String]) {
+  let Some(path) = log_path() else {
+    return;
+  };
+
+  if let Err(e) = append_entry(path, engine, query, is_gif, urls) {
+    warn!("Could not append extracted URLs to {}: {}", path, e);
+  }
+}
+
+fn append_entry(
+  path: &str,
+  engine: &str,
+  query: &str,
+  is_gif: bool,
+  urls: &[String],
+) -> std::io::Result<()> {
+  let ts = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default();
+  let line = json!({
+    "ts": ts,
+    "engine": engine,
+    "query": query,
+    "is_gif": is_gif,
+    "urls": urls,
+  });
+
+  // Concurrent searches must not interleave partial lines
+  let _guard = LOG_LOCK
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner());
+  OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .and_then(|mut file| writeln!(file, "{}", line))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn entries_are_appended_as_jsonl() {
+    let path = std::env::temp_dir().join(format!("tgjpg-extract-{}.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+    let urls = vec!["https://example.com/cat.jpg".to_string()];
+
+    append_entry(path, "Google", "cat \"quoted\"", false, &urls).unwrap();
+    append_entry(path, "Bing", "dog", true, &[]).unwrap();
+    let contents = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    let entries: Vec<serde_json::Value> = contents
+      .lines()
+      .map(|line| serde_json::from_str(line).unwrap())
+      .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["engine"], "Google");
+    assert_eq!(entries[0]["query"], "cat \"quoted\"");
+    assert_eq!(entries[0]["urls"][0], "https://example.com/cat.jpg");
+    assert_eq!(entries[1]["is_gif"], true);
+  }
+}
@@ -1,39 +1,43 @@
 use log::{debug, error, info, warn};
-use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
 use reqwest::StatusCode;
+use reqwest::{Client, RequestBuilder};
+use serde_json::Value;
 use std::collections::HashSet;
+use std::sync::OnceLock;
 
-use crate::image_search::{SearchError, MAX_RESULTS};
+use crate::config_file::setting;
+use crate::image_search::{
+  decode_html, extract_limit, is_blocked_url, is_unwanted_size, url_denylist, SearchError,
+  SearchLocale,
+};
+
+static EXTRA_HEADERS: OnceLock<HeaderMap> = OnceLock::new();
 
 // Search for images using Google Image Search
-pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchError> {
+pub async fn search(
+  query: &str,
+  is_gif: bool,
+  locale: &SearchLocale,
+) -> Result<Vec<String>, SearchError> {
   let endpoint = "https://www.google.com/search";
   let tbs = if is_gif { "ift:gif" } else { "ift:jpg" };
 
   // udm=2 is Google's current image search format (tbm=isch is deprecated and redirects to udm=2)
-  let params = [("q", query), ("tbs", tbs), ("hl", "zh-TW"), ("udm", "2")];
+  let hl = locale.hl();
+  let params = [
+    ("q", query),
+    ("tbs", tbs),
+    ("hl", hl.as_str()),
+    ("udm", "2"),
+  ];
 
   info!(
     "Searching Google Images for query: '{}', is_gif: {}",
     query, is_gif
   );
 
-  let client = Client::new();
-  let res = client
-    .get(endpoint)
-    .query(&params)
-    .header(
-      "User-Agent",
-      "Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.0 Mobile/15E148 Safari/604.1",
-    )
-    .header(
-      "Accept",
-      "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
-    )
-    .header(
-      "Accept-Language",
-      "zh-TW,zh;q=0.9,en-US;q=0.8,en;q=0.7",
-    )
+  let res = google_request(&Client::new(), endpoint, &params, locale, extra_headers())
     .send()
     .await
     .map_err(|err| SearchError::NetworkFailed {
@@ -45,8 +49,12 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
   let final_url = res.url().to_string();
   info!("Received response with status: {}", status);
 
-  // Use bytes() instead of text() for better performance
-  // Only convert the portion we need to UTF-8
+  // Read raw bytes so decoding errors surface explicitly instead of being replaced
+  let content_type = res
+    .headers()
+    .get(CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .map(str::to_string);
   let bytes = res
     .bytes()
     .await
@@ -56,8 +64,7 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
     })?;
   info!("HTML response length: {} bytes", bytes.len());
 
-  // Convert to string (this is the expensive part)
-  let html = String::from_utf8_lossy(&bytes);
+  let html = decode_html("Google", content_type.as_deref(), &bytes)?;
 
   // Log a snippet of the HTML for debugging (first 1000 chars)
   debug!(
@@ -65,16 +72,16 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
     &html.chars().take(1000).collect::<String>()
   );
 
-  if is_blocked(status, &final_url, &html) {
-    error!("Google image search was blocked or challenged.");
+  if let Some(reason) = blocked_reason(status, &final_url, &html) {
+    error!("Google image search was blocked: {}", reason);
     write_debug_html("/tmp/google_search_debug.html", bytes.as_ref());
     return Err(SearchError::Blocked {
       engine: "Google",
-      details: format!("status={}, url={}", status, final_url),
+      details: format!("{}, status={}, url={}", reason, status, final_url),
     });
   }
 
-  let urls = extract_image_urls(&html);
+  let urls = extract_image_urls(&html, extract_limit());
   if urls.is_empty() {
     error!("Google image search returned no parseable image URLs.");
     write_debug_html("/tmp/google_search_debug.html", bytes.as_ref());
@@ -87,8 +94,8 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
   Ok(urls)
 }
 
-// Extract image URLs from Google search results HTML
-fn extract_image_urls(text: &str) -> Vec<String> {
+// Extract up to `limit` image URLs from Google search results HTML
+fn extract_image_urls(text: &str, limit: usize) -> Vec<String> {
   let mut urls = Vec::new();
   let mut seen = HashSet::new();
 
@@ -96,23 +103,31 @@ fn extract_image_urls(text: &str) -> Vec<String> {
   // Pattern: ["https://...image.jpg", width, height]
   // In this JSON structure, each image entry looks like:
   //   [thumbnail_url, th_h, th_w], [original_url, orig_h, orig_w]
-  // We capture the original-size URL (not encrypted-tbn thumbnails)
-  let json_img_regex =
-    regex::Regex::new(r#"\["(https?://[^"]+\.(?:jpg|jpeg|png|gif)[^"]*)"\s*,\s*\d+\s*,\s*\d+\]"#)
-      .unwrap();
+  // We capture the original-size URL (not encrypted-tbn thumbnails) and its dimensions
+  let json_img_regex = regex::Regex::new(
+    r#"\["(https?://[^"]+\.(?:jpg|jpeg|png|gif)[^"]*)"\s*,\s*(\d+)\s*,\s*(\d+)\]"#,
+  )
+  .unwrap();
 
   for cap in json_img_regex.captures_iter(text) {
-    if urls.len() >= MAX_RESULTS {
+    if urls.len() >= limit {
       break; // Early termination once we have enough URLs
     }
 
     if let Some(url_match) = cap.get(1) {
       let url_str = url_match.as_str();
-      // Filter out thumbnails and Google's own images
-      if !url_str.contains("encrypted-tbn")
-        && !url_str.contains("gstatic")
-        && !url_str.contains("googlelogo")
-      {
+      let height = cap.get(2).and_then(|m| m.as_str().parse().ok());
+      let width = cap.get(3).and_then(|m| m.as_str().parse().ok());
+      if is_unwanted_size(width, height) {
+        debug!(
+          "Skipping small or oddly shaped image (method 1): {}",
+          url_str
+        );
+        continue;
+      }
+
+      // Filter out thumbnails, Google's own images and configured domains
+      if !is_blocked_url(url_str, url_denylist()) {
         // Decode unicode escapes if any
         let url = url_str.replace("\\u0026", "&").replace("\\u003d", "=");
         // Deduplicate: Google embeds the same JSON data multiple times in the page
@@ -133,17 +148,14 @@ fn extract_image_urls(text: &str) -> Vec<String> {
       regex::Regex::new(r#""(https?://[^"]+\.(?:jpg|jpeg|png|gif)[^"]*)""#).unwrap();
 
     for cap in quoted_url_regex.captures_iter(text) {
-      if urls.len() >= MAX_RESULTS {
+      if urls.len() >= limit {
         break;
       }
 
       if let Some(url_match) = cap.get(1) {
         let url_str = url_match.as_str();
         // Filter out thumbnails
-        if !url_str.contains("encrypted-tbn")
-          && !url_str.contains("gstatic")
-          && !url_str.contains("googlelogo")
-        {
+        if !is_blocked_url(url_str, url_denylist()) {
           let url = url_str.replace("\\u0026", "&").replace("\\u003d", "=");
           if seen.insert(url.clone()) {
             debug!("Extracted URL (method 2): {}", url);
@@ -160,10 +172,7 @@ fn extract_image_urls(text: &str) -> Vec<String> {
   if urls.is_empty() {
     info!("Method 2 failed, trying method 3 (data-ou)");
     let data_ou_regex = regex::Regex::new(r#"data-ou="(.*?)""#).unwrap();
-    let data_ou_matches: Vec<_> = data_ou_regex
-      .captures_iter(text)
-      .take(MAX_RESULTS)
-      .collect();
+    let data_ou_matches: Vec<_> = data_ou_regex.captures_iter(text).take(limit).collect();
     info!(
       "Method 3 (data-ou): Found {} matches",
       data_ou_matches.len()
@@ -187,13 +196,85 @@ fn extract_image_urls(text: &str) -> Vec<String> {
   urls
 }
 
-fn is_blocked(status: StatusCode, final_url: &str, html: &str) -> bool {
-  status == StatusCode::TOO_MANY_REQUESTS
-    || final_url.contains("/sorry/")
+// The results page request, with GOOGLE_EXTRA_HEADERS applied over the browser-like defaults
+fn google_request(
+  client: &Client,
+  endpoint: &str,
+  params: &[(&str, &str)],
+  locale: &SearchLocale,
+  extra_headers: &HeaderMap,
+) -> RequestBuilder {
+  client
+    .get(endpoint)
+    .query(params)
+    .header(
+      "User-Agent",
+      "Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.0 Mobile/15E148 Safari/604.1",
+    )
+    .header(
+      "Accept",
+      "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+    )
+    .header("Accept-Language", locale.accept_language())
+    .headers(extra_headers.clone())
+}
+
+// Headers added to every Google request, replacing defaults of the same name, from
+// GOOGLE_EXTRA_HEADERS: a JSON object such as {"Cookie": "CONSENT=YES+"} to get past
+// the EU consent page. Entries that aren't valid headers are skipped with a warning.
+fn extra_headers() -> &'static HeaderMap {
+  EXTRA_HEADERS.get_or_init(|| match setting("GOOGLE_EXTRA_HEADERS") {
+    Ok(value) => parse_extra_headers(&value),
+    Err(_) => HeaderMap::new(),
+  })
+}
+
+fn parse_extra_headers(value: &str) -> HeaderMap {
+  let mut headers = HeaderMap::new();
+  let entries = match serde_json::from_str::<Value>(value) {
+    Ok(Value::Object(entries)) => entries,
+    _ => {
+      warn!("GOOGLE_EXTRA_HEADERS must be a JSON object of header name to value; ignoring it");
+      return headers;
+    }
+  };
+  for (name, value) in entries {
+    let header = value.as_str().and_then(|value| {
+      let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+      let value = HeaderValue::from_str(value).ok()?;
+      Some((name, value))
+    });
+    match header {
+      Some((name, value)) => {
+        headers.insert(name, value);
+      }
+      None => warn!("Skipping invalid header '{}' in GOOGLE_EXTRA_HEADERS", name),
+    }
+  }
+  headers
+}
+
+// Name the interstitial Google served instead of results, if any, so it isn't
+// mistaken for a layout change when no URLs can be extracted
+fn blocked_reason(status: StatusCode, final_url: &str, html: &str) -> Option<&'static str> {
+  if status == StatusCode::TOO_MANY_REQUESTS {
+    Some("rate limited")
+  } else if final_url.contains("consent.google.")
+    || html.contains("action=\"https://consent.google.com/save\"")
+    || html.contains("Before you continue to Google")
+  {
+    Some("consent page")
+  } else if final_url.contains("/sorry/")
     || html.contains("g-recaptcha")
     || html.contains("異常流量")
     || html.contains("unusual traffic")
-    || html.contains("/httpservice/retry/enablejs")
+  {
+    Some("captcha")
+  } else if html.contains("/httpservice/retry/enablejs") {
+    Some("JavaScript check")
+  } else {
+    None
+  }
 }
 
 fn write_debug_html(path: &str, bytes: &[u8]) {
@@ -203,3 +284,77 @@ fn write_debug_html(path: &str, bytes: &[u8]) {
     info!("Wrote full HTML response to {} for debugging", path);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn consent_page_is_reported_as_blocked() {
+    let html = r#"<html><body><h1>Before you continue to Google</h1>
+      <form action="https://consent.google.com/save" method="POST"></form></body></html>"#;
+    assert_eq!(
+      blocked_reason(
+        StatusCode::OK,
+        "https://consent.google.com/ml?continue=https://www.google.com/search",
+        html
+      ),
+      Some("consent page")
+    );
+  }
+
+  #[test]
+  fn captcha_page_is_reported_as_blocked() {
+    let html = r#"<html><body><div>Our systems have detected unusual traffic from your
+      computer network.</div><div class="g-recaptcha"></div></body></html>"#;
+    assert_eq!(
+      blocked_reason(StatusCode::OK, "https://www.google.com/sorry/index", html),
+      Some("captcha")
+    );
+    assert_eq!(
+      blocked_reason(
+        StatusCode::OK,
+        "https://www.google.com/search",
+        "<html></html>"
+      ),
+      None
+    );
+  }
+
+  #[test]
+  fn extract_limit_caps_the_urls_collected() {
+    let html: String = (1..=30)
+      .map(|n| format!(r#"["https://example.com/img{}.jpg",800,1000],"#, n))
+      .collect();
+
+    assert_eq!(extract_image_urls(&html, 5).len(), 5);
+    let urls = extract_image_urls(&html, 12);
+    assert_eq!(urls.len(), 12);
+    assert_eq!(urls[11], "https://example.com/img12.jpg");
+  }
+
+  #[test]
+  fn configured_headers_are_applied_to_the_request() {
+    let extra = parse_extra_headers(
+      r#"{"Cookie": "CONSENT=YES+", "Accept-Language": "en", "bad header": "x", "X-Count": 1}"#,
+    );
+    assert_eq!(extra.len(), 2);
+
+    let request = google_request(
+      &Client::new(),
+      "https://www.google.com/search",
+      &[("q", "cat")],
+      &SearchLocale::default(),
+      &extra,
+    )
+    .build()
+    .unwrap();
+
+    let headers = request.headers();
+    assert_eq!(headers["cookie"], "CONSENT=YES+");
+    // Configured headers replace the defaults of the same name
+    assert_eq!(headers["accept-language"], "en");
+    assert!(headers.contains_key("user-agent"));
+    assert_eq!(request.url().query(), Some("q=cat"));
+  }
+}
@@ -0,0 +1,122 @@
+use crate::config_file::setting;
+
+const DEFAULT_LANGUAGE: &str = "zh";
+const DEFAULT_REGION: &str = "TW";
+
+// Language/region used for search parameters (hl, gl) and the Accept-Language header
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SearchLocale {
+  language: String,
+  region: Option<String>,
+}
+
+impl Default for SearchLocale {
+  fn default() -> Self {
+    SearchLocale {
+      language: DEFAULT_LANGUAGE.to_string(),
+      region: Some(DEFAULT_REGION.to_string()),
+    }
+  }
+}
+
+impl SearchLocale {
+  // SEARCH_LOCALE wins, then the user's Telegram language_code, then zh-TW
+  pub fn resolve(user_language: Option<&str>) -> Self {
+    Self::resolve_from(setting("SEARCH_LOCALE").ok().as_deref(), user_language)
+  }
+
+  fn resolve_from(configured: Option<&str>, user_language: Option<&str>) -> Self {
+    configured
+      .and_then(Self::parse)
+      .or_else(|| user_language.and_then(Self::parse))
+      .unwrap_or_default()
+  }
+
+  // Parse an IETF tag as sent by Telegram ("en", "pt-br", "zh-hant") into a locale
+  pub fn parse(tag: &str) -> Option<Self> {
+    let mut parts = tag.trim().split(['-', '_']);
+    let language = parts.next()?.to_lowercase();
+    if language.len() < 2 || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+      return None;
+    }
+
+    let region = match parts.next().map(str::to_lowercase).as_deref() {
+      // Script subtags: Traditional is most commonly Taiwan, Simplified mainland China
+      Some("hant") => Some("TW".to_string()),
+      Some("hans") => Some("CN".to_string()),
+      Some(region) if region.len() == 2 => Some(region.to_uppercase()),
+      _ => None,
+    };
+
+    Some(SearchLocale { language, region })
+  }
+
+  // Google-style interface language, e.g. "zh-TW" or "en"
+  pub fn hl(&self) -> String {
+    match &self.region {
+      Some(region) => format!("{}-{}", self.language, region),
+      None => self.language.clone(),
+    }
+  }
+
+  // Lowercase country code for APIs that take one separately, e.g. "tw"
+  pub fn gl(&self) -> Option<String> {
+    self.region.as_ref().map(|region| region.to_lowercase())
+  }
+
+  // e.g. "zh-TW,zh;q=0.9,en-US;q=0.8,en;q=0.7", always keeping English as a fallback
+  pub fn accept_language(&self) -> String {
+    let mut parts = match &self.region {
+      Some(_) => vec![self.hl(), format!("{};q=0.9", self.language)],
+      None => vec![self.language.clone()],
+    };
+    if self.language != "en" {
+      parts.push("en-US;q=0.8".to_string());
+      parts.push("en;q=0.7".to_string());
+    }
+    parts.join(",")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn script_subtags_map_to_regions() {
+    let locale = SearchLocale::parse("zh-hant").unwrap();
+    assert_eq!(locale.hl(), "zh-TW");
+    assert_eq!(
+      locale.accept_language(),
+      "zh-TW,zh;q=0.9,en-US;q=0.8,en;q=0.7"
+    );
+  }
+
+  #[test]
+  fn region_subtags_are_kept() {
+    let locale = SearchLocale::parse("pt-BR").unwrap();
+    assert_eq!(locale.hl(), "pt-BR");
+    assert_eq!(locale.gl().as_deref(), Some("br"));
+    assert_eq!(
+      locale.accept_language(),
+      "pt-BR,pt;q=0.9,en-US;q=0.8,en;q=0.7"
+    );
+  }
+
+  #[test]
+  fn empty_code_falls_back_to_default() {
+    assert_eq!(SearchLocale::parse(""), None);
+    assert_eq!(
+      SearchLocale::resolve_from(None, Some("")),
+      SearchLocale::default()
+    );
+  }
+
+  #[test]
+  fn configured_locale_beats_user_language() {
+    let locale = SearchLocale::resolve_from(Some("en"), Some("ja"));
+    assert_eq!(locale.hl(), "en");
+    assert_eq!(locale.accept_language(), "en");
+    assert_eq!(SearchLocale::resolve_from(None, Some("ja")).hl(), "ja");
+  }
+}
@@ -1,22 +1,44 @@
 use anyhow::{anyhow, Result};
+use encoding_rs::{Encoding, UTF_8};
 use log::{info, warn};
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt;
-use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config_file::setting;
 
 pub mod bing;
 pub mod ddg;
+mod extract_log;
 pub mod google;
+mod locale;
 pub mod serpapi;
 pub mod serper;
+pub mod success_rate;
+
+pub use locale::SearchLocale;
 
-pub(crate) const MAX_RESULTS: usize = 10;
+// More candidates than are ever sent, so the send loop has fallbacks when URLs are dead
+const DEFAULT_CANDIDATE_LIMIT: usize = 20;
+const MAX_CANDIDATE_LIMIT: usize = 100;
 const HEALTH_CHECK_QUERY: &str = "cat";
+const DEFAULT_MIN_IMAGE_DIMENSION: u64 = 200;
 static ENABLED_ENGINES: OnceLock<Vec<SearchEngine>> = OnceLock::new();
+// Thumbnails and Google's own assets, never useful as results
+const DEFAULT_BLOCKED_URL_PATTERNS: &[&str] = &["encrypted-tbn", "gstatic", "googlelogo"];
+static URL_DENYLIST: OnceLock<Vec<String>> = OnceLock::new();
+static PREFERRED_URL_PATTERNS: OnceLock<Vec<String>> = OnceLock::new();
+const DEFAULT_SEARCH_CACHE_TTL_SECS: u64 = 300;
+const SEARCH_CACHE_CAPACITY: usize = 200;
+static SEARCH_CACHE: OnceLock<Mutex<VecDeque<CachedSearch>>> = OnceLock::new();
+static SEARCHES_IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum SearchEngine {
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SearchEngine {
   Serper,
   Google,
   SerpApi,
@@ -25,7 +47,22 @@ enum SearchEngine {
 }
 
 impl SearchEngine {
-  fn label(self) -> &'static str {
+  pub const ALL: [SearchEngine; 5] = [
+    SearchEngine::Serper,
+    SearchEngine::Google,
+    SearchEngine::SerpApi,
+    SearchEngine::Ddg,
+    SearchEngine::Bing,
+  ];
+
+  // Look up an engine by its label, ignoring case ("google", "serpapi", "ddg")
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL
+      .into_iter()
+      .find(|engine| engine.label().eq_ignore_ascii_case(name.trim()))
+  }
+
+  pub fn label(self) -> &'static str {
     match self {
       SearchEngine::Serper => "Serper",
       SearchEngine::Google => "Google",
@@ -53,6 +90,10 @@ pub enum SearchError {
     engine: &'static str,
     details: String,
   },
+  TimedOut {
+    engine: &'static str,
+    after: Duration,
+  },
 }
 
 impl fmt::Display for SearchError {
@@ -68,12 +109,27 @@ impl fmt::Display for SearchError {
       SearchError::NetworkFailed { engine, details } => {
         write!(f, "{} network failed: {}", engine, details)
       }
+      SearchError::TimedOut { engine, after } => {
+        write!(
+          f,
+          "{} gave no response within {} ms",
+          engine,
+          after.as_millis()
+        )
+      }
     }
   }
 }
 
 impl Error for SearchError {}
 
+// An image URL annotated with the engine that produced it
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+  pub url: String,
+  pub engine: SearchEngine,
+}
+
 pub async fn initialize() {
   if ENABLED_ENGINES.get().is_some() {
     return;
@@ -92,30 +148,33 @@ pub async fn initialize() {
     info!("SERP_API not set; SerpAPI image search is disabled.");
   }
 
-  let (serper_result, google_result, serpapi_result, ddg_result, bing_result) = tokio::join!(
-    run_optional_search(
-      serper_enabled,
-      SearchEngine::Serper,
-      HEALTH_CHECK_QUERY,
-      false
-    ),
-    google::search(HEALTH_CHECK_QUERY, false),
+  let chain = configured_chain();
+  let locale = SearchLocale::default();
+  let health_check = |engine| {
     run_optional_search(
-      serpapi_enabled,
-      SearchEngine::SerpApi,
+      chain.contains(&engine),
+      engine,
       HEALTH_CHECK_QUERY,
-      false
-    ),
-    ddg::search(HEALTH_CHECK_QUERY, false),
-    bing::search(HEALTH_CHECK_QUERY, false)
+      false,
+      &locale,
+      None,
+    )
+  };
+  let (serper_result, google_result, serpapi_result, ddg_result, bing_result) = tokio::join!(
+    health_check(SearchEngine::Serper),
+    health_check(SearchEngine::Google),
+    health_check(SearchEngine::SerpApi),
+    health_check(SearchEngine::Ddg),
+    health_check(SearchEngine::Bing)
   );
 
   let mut enabled = Vec::new();
   update_health_optional(&mut enabled, SearchEngine::Serper, serper_result);
-  update_health(&mut enabled, SearchEngine::Google, google_result);
+  update_health_optional(&mut enabled, SearchEngine::Google, google_result);
   update_health_optional(&mut enabled, SearchEngine::SerpApi, serpapi_result);
-  update_health(&mut enabled, SearchEngine::Ddg, ddg_result);
-  update_health(&mut enabled, SearchEngine::Bing, bing_result);
+  update_health_optional(&mut enabled, SearchEngine::Ddg, ddg_result);
+  update_health_optional(&mut enabled, SearchEngine::Bing, bing_result);
+  enabled.sort_by_key(|engine| chain.iter().position(|chained| chained == engine));
 
   if enabled.is_empty() {
     warn!("No image search engines passed the startup health check.");
@@ -133,11 +192,34 @@ pub async fn initialize() {
   let _ = ENABLED_ENGINES.set(enabled);
 }
 
-pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, anyhow::Error> {
+pub async fn search(
+  query: &str,
+  is_gif: bool,
+  locale: &SearchLocale,
+) -> Result<Vec<SearchResult>, anyhow::Error> {
+  search_within(query, is_gif, locale, None).await
+}
+
+// Like search, but engines still running after `deadline` are dropped so callers
+// with a hard response window (inline queries) get whatever the faster engines found
+pub async fn search_within(
+  query: &str,
+  is_gif: bool,
+  locale: &SearchLocale,
+  deadline: Option<Duration>,
+) -> Result<Vec<SearchResult>, anyhow::Error> {
+  let cache_key = search_cache_key(query, is_gif, locale);
+  let ttl = search_cache_ttl();
+  if let Some(results) = cached_search(&cache_key, ttl) {
+    info!("Using cached results for query '{}'", query);
+    return Ok(results);
+  }
+
+  let _in_flight = SearchInFlight::start();
   let enabled = ENABLED_ENGINES
     .get()
     .cloned()
-    .unwrap_or_else(default_engines);
+    .unwrap_or_else(configured_chain);
 
   let use_serper = enabled.contains(&SearchEngine::Serper);
   let use_google = enabled.contains(&SearchEngine::Google);
@@ -146,58 +228,80 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, anyhow::Er
   let use_bing = enabled.contains(&SearchEngine::Bing);
 
   let (serper_result, google_result, serpapi_result, ddg_result, bing_result) = tokio::join!(
-    run_optional_search(use_serper, SearchEngine::Serper, query, is_gif),
-    run_optional_search(use_google, SearchEngine::Google, query, is_gif),
-    run_optional_search(use_serpapi, SearchEngine::SerpApi, query, is_gif),
-    run_optional_search(use_ddg, SearchEngine::Ddg, query, is_gif),
-    run_optional_search(use_bing, SearchEngine::Bing, query, is_gif)
+    run_optional_search(
+      use_serper,
+      SearchEngine::Serper,
+      query,
+      is_gif,
+      locale,
+      deadline
+    ),
+    run_optional_search(
+      use_google,
+      SearchEngine::Google,
+      query,
+      is_gif,
+      locale,
+      deadline
+    ),
+    run_optional_search(
+      use_serpapi,
+      SearchEngine::SerpApi,
+      query,
+      is_gif,
+      locale,
+      deadline
+    ),
+    run_optional_search(use_ddg, SearchEngine::Ddg, query, is_gif, locale, deadline),
+    run_optional_search(
+      use_bing,
+      SearchEngine::Bing,
+      query,
+      is_gif,
+      locale,
+      deadline
+    )
   );
 
+  // Results cut short by the deadline would shortchange later callers, so don't cache them
+  let timed_out = [
+    &serper_result,
+    &google_result,
+    &serpapi_result,
+    &ddg_result,
+    &bing_result,
+  ]
+  .iter()
+  .any(|result| matches!(result, Some(Err(SearchError::TimedOut { .. }))));
+
   let mut combined = Vec::new();
   let mut seen = HashSet::new();
   let mut had_success = false;
   let mut errors = Vec::new();
 
-  merge_results(
-    "Serper",
-    serper_result,
-    &mut combined,
-    &mut seen,
-    &mut had_success,
-    &mut errors,
-  );
-  merge_results(
-    "Google",
-    google_result,
-    &mut combined,
-    &mut seen,
-    &mut had_success,
-    &mut errors,
-  );
-  merge_results(
-    "SerpAPI",
-    serpapi_result,
-    &mut combined,
-    &mut seen,
-    &mut had_success,
-    &mut errors,
-  );
-  merge_results(
-    "DDG",
-    ddg_result,
-    &mut combined,
-    &mut seen,
-    &mut had_success,
-    &mut errors,
-  );
-  merge_results(
-    "Bing",
-    bing_result,
-    &mut combined,
-    &mut seen,
-    &mut had_success,
-    &mut errors,
-  );
+  // Merge in chain order so earlier engines' URLs are tried first
+  let mut results = [
+    (SearchEngine::Serper, serper_result),
+    (SearchEngine::Google, google_result),
+    (SearchEngine::SerpApi, serpapi_result),
+    (SearchEngine::Ddg, ddg_result),
+    (SearchEngine::Bing, bing_result),
+  ];
+  for engine in &enabled {
+    if let Some((_, result)) = results
+      .iter_mut()
+      .find(|(candidate, _)| candidate == engine)
+    {
+      merge_results(
+        *engine,
+        result.take(),
+        &mut combined,
+        &mut seen,
+        &mut had_success,
+        &mut errors,
+      );
+    }
+  }
 
   if combined.is_empty() {
     if had_success {
@@ -212,6 +316,11 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, anyhow::Er
     ));
   }
 
+  prefer_url_patterns(&mut combined, preferred_url_patterns());
+  if !timed_out && !ttl.is_zero() {
+    cache_search(cache_key, &combined);
+  }
+
   info!(
     "Combined image search returned {} URLs for query '{}'",
     combined.len(),
@@ -221,9 +330,9 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, anyhow::Er
 }
 
 fn merge_results(
-  source: &str,
+  engine: SearchEngine,
   result: Option<std::result::Result<Vec<String>, SearchError>>,
-  combined: &mut Vec<String>,
+  combined: &mut Vec<SearchResult>,
   seen: &mut HashSet<String>,
   had_success: &mut bool,
   errors: &mut Vec<String>,
@@ -232,24 +341,390 @@ fn merge_results(
     return;
   };
 
+  // A deadline cut-off says nothing about whether the engine still works
+  if !matches!(result, Err(SearchError::TimedOut { .. })) {
+    success_rate::record_outcome(engine, result.is_ok());
+  }
+
   match result {
     Ok(urls) => {
       *had_success = true;
-      info!("{} returned {} URLs", source, urls.len());
+      info!("{} returned {} URLs", engine.label(), urls.len());
 
       for url in urls {
-        if combined.len() >= MAX_RESULTS {
+        if combined.len() >= candidate_limit() {
           break;
         }
 
+        if !is_fetchable_url(&url) || is_blocked_url(&url, url_denylist()) {
+          continue;
+        }
+        // Decoding replaced invalid bytes in this URL, so it no longer points at the image
+        if url.contains(char::REPLACEMENT_CHARACTER) {
+          warn!(
+            "Skipping {} URL damaged by invalid bytes: {}",
+            engine.label(),
+            url
+          );
+          continue;
+        }
+
         if seen.insert(url.clone()) {
-          combined.push(url);
+          combined.push(SearchResult { url, engine });
         }
       }
     }
     Err(err) => {
-      warn!("{} image search failed: {}", source, err);
-      errors.push(format!("{}: {}", source, err));
+      warn!("{} image search failed: {}", engine.label(), err);
+      errors.push(format!("{}: {}", engine.label(), err));
+    }
+  }
+}
+
+// How many candidate URLs the combined search returns.
+// Read from SEARCH_CANDIDATE_LIMIT; independent of how many images are actually sent.
+pub fn candidate_limit() -> usize {
+  setting("SEARCH_CANDIDATE_LIMIT")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .filter(|limit| *limit > 0)
+    .unwrap_or(DEFAULT_CANDIDATE_LIMIT)
+    .min(MAX_CANDIDATE_LIMIT)
+}
+
+// How many URLs each engine's extractor collects before it stops scanning the page.
+// Read from SEARCH_EXTRACT_LIMIT (default SEARCH_CANDIDATE_LIMIT): raise it to keep more
+// fallbacks for dead links, lower it to stop parsing sooner.
+pub fn extract_limit() -> usize {
+  setting("SEARCH_EXTRACT_LIMIT")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .filter(|limit| *limit > 0)
+    .unwrap_or_else(candidate_limit)
+    .min(MAX_CANDIDATE_LIMIT)
+}
+
+// Counts a search that is waiting on the engines; dropped on completion or when a timeout
+// abandons the search
+struct SearchInFlight;
+
+impl SearchInFlight {
+  fn start() -> Self {
+    SEARCHES_IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    SearchInFlight
+  }
+}
+
+impl Drop for SearchInFlight {
+  fn drop(&mut self) {
+    SEARCHES_IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+// Searches currently waiting on the engines (cache hits aren't counted)
+pub fn searches_in_flight() -> usize {
+  SEARCHES_IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+// Messages and inline queries share the cache, so the key only depends on what is searched
+fn search_cache_key(query: &str, is_gif: bool, locale: &SearchLocale) -> String {
+  format!(
+    "{}|{}|{}",
+    query
+      .split_whitespace()
+      .collect::<Vec<_>>()
+      .join(" ")
+      .to_lowercase(),
+    is_gif,
+    locale.hl()
+  )
+}
+
+// How long combined results are reused, from SEARCH_CACHE_TTL_SECS (0 disables)
+fn search_cache_ttl() -> Duration {
+  let secs = setting("SEARCH_CACHE_TTL_SECS")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(DEFAULT_SEARCH_CACHE_TTL_SECS);
+  Duration::from_secs(secs)
+}
+
+// Combined results for one cache key, reused until the TTL runs out
+struct CachedSearch {
+  key: String,
+  searched_at: Instant,
+  results: Vec<SearchResult>,
+}
+
+fn cached_search(key: &str, ttl: Duration) -> Option<Vec<SearchResult>> {
+  let mut cache = search_cache();
+  cache.retain(|cached| cached.searched_at.elapsed() < ttl);
+  cache
+    .iter()
+    .find(|cached| cached.key == key)
+    .map(|cached| cached.results.clone())
+}
+
+fn cache_search(key: String, results: &[SearchResult]) {
+  let mut cache = search_cache();
+  cache.retain(|cached| cached.key != key);
+  if cache.len() >= SEARCH_CACHE_CAPACITY {
+    cache.pop_front();
+  }
+  cache.push_back(CachedSearch {
+    key,
+    searched_at: Instant::now(),
+    results: results.to_vec(),
+  });
+}
+
+// Drop every cached search, returning how many entries were removed
+pub fn clear_search_cache() -> usize {
+  let mut cache = search_cache();
+  let cleared = cache.len();
+  cache.clear();
+  cleared
+}
+
+fn search_cache() -> MutexGuard<'static, VecDeque<CachedSearch>> {
+  SEARCH_CACHE
+    .get_or_init(|| Mutex::new(VecDeque::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Substrings marking high-quality URLs (e.g. "_1280.") from PREFERRED_URL_PATTERNS, in priority order
+fn preferred_url_patterns() -> &'static [String] {
+  PREFERRED_URL_PATTERNS.get_or_init(|| {
+    setting("PREFERRED_URL_PATTERNS")
+      .map(|value| {
+        value
+          .split(',')
+          .map(|pattern| pattern.trim().to_lowercase())
+          .filter(|pattern| !pattern.is_empty())
+          .collect()
+      })
+      .unwrap_or_default()
+  })
+}
+
+// Move results matching an earlier pattern ahead of later ones and non-matches,
+// keeping the original order within each group
+fn prefer_url_patterns(results: &mut [SearchResult], patterns: &[String]) {
+  if patterns.is_empty() {
+    return;
+  }
+
+  results.sort_by_key(|result| {
+    let url = result.url.to_lowercase();
+    patterns
+      .iter()
+      .position(|pattern| url.contains(pattern.as_str()))
+      .unwrap_or(patterns.len())
+  });
+}
+
+// Decode a scraped HTML body as declared by its Content-Type (UTF-8 when absent).
+// Bytes that aren't valid in that charset become U+FFFD; merge_results drops the URLs they
+// land in, so one bad sequence doesn't cost the whole page.
+pub(crate) fn decode_html(
+  engine: &'static str,
+  content_type: Option<&str>,
+  bytes: &[u8],
+) -> Result<String, SearchError> {
+  let charset = content_type.and_then(|content_type| {
+    content_type.split(';').find_map(|param| {
+      let (key, value) = param.split_once('=')?;
+      key
+        .trim()
+        .eq_ignore_ascii_case("charset")
+        .then(|| value.trim().trim_matches('"').to_string())
+    })
+  });
+  let encoding = match charset {
+    Some(charset) => {
+      Encoding::for_label(charset.as_bytes()).ok_or_else(|| SearchError::ParseFailed {
+        engine,
+        details: format!("Unsupported charset: {}", charset),
+      })?
+    }
+    None => UTF_8,
+  };
+
+  let (html, had_errors) = encoding.decode_without_bom_handling(bytes);
+  if had_errors {
+    warn!(
+      "{} response is not valid {}; URLs with invalid bytes will be skipped",
+      engine,
+      encoding.name()
+    );
+  }
+  Ok(html.into_owned())
+}
+
+// Telegram can only fetch http(s) URLs; data:, ftp: and the like would fail at send time
+pub(crate) fn is_fetchable_url(url: &str) -> bool {
+  url::Url::parse(url)
+    .map(|parsed| matches!(parsed.scheme(), "http" | "https"))
+    .unwrap_or(false)
+}
+
+// Default patterns plus BLOCKED_URL_PATTERNS (comma-separated, e.g. domains serving watermarks)
+pub(crate) fn url_denylist() -> &'static [String] {
+  URL_DENYLIST.get_or_init(|| {
+    let mut denylist: Vec<String> = DEFAULT_BLOCKED_URL_PATTERNS
+      .iter()
+      .map(|pattern| pattern.to_string())
+      .collect();
+
+    if let Ok(value) = setting("BLOCKED_URL_PATTERNS") {
+      denylist.extend(
+        value
+          .split(',')
+          .map(|pattern| pattern.trim().to_lowercase())
+          .filter(|pattern| !pattern.is_empty()),
+      );
+    }
+
+    denylist
+  })
+}
+
+// A URL is blocked when its host is a denylisted domain or one of its subdomains, or when it
+// contains any other denylist entry ("gstatic", "/thumbs/"). Case-insensitive.
+pub(crate) fn is_blocked_url(url: &str, denylist: &[String]) -> bool {
+  let url = url.to_lowercase();
+  let host = url::Url::parse(&url)
+    .ok()
+    .and_then(|parsed| parsed.host_str().map(str::to_string));
+  denylist.iter().any(|pattern| {
+    if is_domain_pattern(pattern) {
+      host
+        .as_deref()
+        .is_some_and(|host| host == pattern || host.ends_with(&format!(".{}", pattern)))
+    } else {
+      url.contains(pattern.as_str())
+    }
+  })
+}
+
+// "watermark-site.com" names a host, so "notwatermark-site.com" mustn't match it
+fn is_domain_pattern(pattern: &str) -> bool {
+  pattern.contains('.') && !pattern.contains('/') && !pattern.starts_with('.')
+}
+
+// Skip results whose reported dimensions are unwanted: icon-sized or outside the allowed
+// aspect ratio. Unknown sizes pass through.
+pub(crate) fn is_unwanted_size(width: Option<u64>, height: Option<u64>) -> bool {
+  is_below_min_resolution(width, height) || is_outside_aspect_ratio(width, height)
+}
+
+// Thresholds come from MIN_IMAGE_WIDTH / MIN_IMAGE_HEIGHT (default 200x200, 0 disables).
+fn is_below_min_resolution(width: Option<u64>, height: Option<u64>) -> bool {
+  let (min_width, min_height) = (
+    read_min_dimension("MIN_IMAGE_WIDTH"),
+    read_min_dimension("MIN_IMAGE_HEIGHT"),
+  );
+
+  width.is_some_and(|width| width < min_width) || height.is_some_and(|height| height < min_height)
+}
+
+// Width / height bounds from MIN_ASPECT_RATIO / MAX_ASPECT_RATIO (e.g. 0.5 and 2), off by default.
+// Needs both dimensions, so results missing either one pass through.
+fn is_outside_aspect_ratio(width: Option<u64>, height: Option<u64>) -> bool {
+  let bounds = (
+    read_aspect_ratio("MIN_ASPECT_RATIO"),
+    read_aspect_ratio("MAX_ASPECT_RATIO"),
+  );
+  is_outside_ratio_bounds(width, height, bounds)
+}
+
+fn is_outside_ratio_bounds(
+  width: Option<u64>,
+  height: Option<u64>,
+  (min_ratio, max_ratio): (Option<f64>, Option<f64>),
+) -> bool {
+  let (Some(width), Some(height)) = (width, height) else {
+    return false;
+  };
+  if height == 0 {
+    return false;
+  }
+
+  let ratio = width as f64 / height as f64;
+  min_ratio.is_some_and(|min| ratio < min) || max_ratio.is_some_and(|max| ratio > max)
+}
+
+fn read_aspect_ratio(key: &str) -> Option<f64> {
+  let value = setting(key).ok()?;
+  match value.trim().parse::<f64>() {
+    Ok(ratio) if ratio > 0.0 => Some(ratio),
+    _ => {
+      warn!("Invalid {} value '{}'; ignoring it", key, value);
+      None
+    }
+  }
+}
+
+fn read_min_dimension(key: &str) -> u64 {
+  match setting(key) {
+    Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+      warn!(
+        "Invalid {} value '{}'; using default {}",
+        key, value, DEFAULT_MIN_IMAGE_DIMENSION
+      );
+      DEFAULT_MIN_IMAGE_DIMENSION
+    }),
+    Err(_) => DEFAULT_MIN_IMAGE_DIMENSION,
+  }
+}
+
+// Engines named in priority order, e.g. ["bing", "google"]; unknown names are an error
+pub fn build_chain(names: &[&str]) -> Result<Vec<SearchEngine>> {
+  let mut chain = Vec::new();
+  for name in names {
+    let engine =
+      SearchEngine::from_name(name).ok_or_else(|| anyhow!("unknown search engine: {}", name))?;
+    if !chain.contains(&engine) {
+      chain.push(engine);
+    }
+  }
+  Ok(chain)
+}
+
+// SEARCH_ENGINES (comma-separated names) selects and orders the engines to use;
+// otherwise every available engine in the default order
+fn configured_chain() -> Vec<SearchEngine> {
+  let Ok(value) = setting("SEARCH_ENGINES") else {
+    return default_engines();
+  };
+  let names: Vec<&str> = value
+    .split(',')
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+    .collect();
+  if names.is_empty() {
+    return default_engines();
+  }
+
+  match build_chain(&names) {
+    Ok(mut chain) => {
+      chain.retain(|engine| match engine {
+        SearchEngine::Serper if !serper::is_configured() => {
+          warn!("SEARCH_ENGINES names Serper but SERPER_API is not set; skipping it");
+          false
+        }
+        SearchEngine::SerpApi if !serpapi::is_configured() => {
+          warn!("SEARCH_ENGINES names SerpAPI but SERP_API is not set; skipping it");
+          false
+        }
+        _ => true,
+      });
+      chain
+    }
+    Err(e) => {
+      warn!("Ignoring SEARCH_ENGINES: {}", e);
+      default_engines()
     }
   }
 }
@@ -271,18 +746,47 @@ async fn run_optional_search(
   engine: SearchEngine,
   query: &str,
   is_gif: bool,
+  locale: &SearchLocale,
+  deadline: Option<Duration>,
 ) -> Option<std::result::Result<Vec<String>, SearchError>> {
   if !enabled {
     return None;
   }
 
-  Some(match engine {
-    SearchEngine::Serper => serper::search(query, is_gif).await,
-    SearchEngine::Google => google::search(query, is_gif).await,
-    SearchEngine::SerpApi => serpapi::search(query, is_gif).await,
-    SearchEngine::Ddg => ddg::search(query, is_gif).await,
-    SearchEngine::Bing => bing::search(query, is_gif).await,
-  })
+  let search = async {
+    match engine {
+      SearchEngine::Serper => serper::search(query, is_gif, locale).await,
+      SearchEngine::Google => google::search(query, is_gif, locale).await,
+      SearchEngine::SerpApi => serpapi::search(query, is_gif, locale).await,
+      SearchEngine::Ddg => ddg::search(query, is_gif, locale).await,
+      SearchEngine::Bing => bing::search(query, is_gif, locale).await,
+    }
+  };
+
+  let result = within_deadline(engine, deadline, search).await;
+  if let Ok(urls) = &result {
+    extract_log::log_extracted(engine.label(), query, is_gif, urls);
+  }
+  Some(result)
+}
+
+// Cut an engine's search off at the deadline, if any, so callers answer with what arrived
+async fn within_deadline(
+  engine: SearchEngine,
+  deadline: Option<Duration>,
+  search: impl std::future::Future<Output = std::result::Result<Vec<String>, SearchError>>,
+) -> std::result::Result<Vec<String>, SearchError> {
+  match deadline {
+    Some(deadline) => tokio::time::timeout(deadline, search)
+      .await
+      .unwrap_or_else(|_| {
+        Err(SearchError::TimedOut {
+          engine: engine.label(),
+          after: deadline,
+        })
+      }),
+    None => search.await,
+  }
 }
 
 fn update_health(
@@ -309,3 +813,263 @@ fn update_health_optional(
     update_health(enabled, engine, result);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Held by tests that flush or rely on the shared search cache
+  fn search_cache_lock() -> &'static tokio::sync::Mutex<()> {
+    static LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| tokio::sync::Mutex::new(()))
+  }
+
+  fn result(url: &str) -> SearchResult {
+    SearchResult {
+      url: url.to_string(),
+      engine: SearchEngine::Google,
+    }
+  }
+
+  #[test]
+  fn flushed_query_misses_the_cache() {
+    let _cache = search_cache_lock().blocking_lock();
+    let key = search_cache_key("flush me", false, &SearchLocale::default());
+    let ttl = Duration::from_secs(300);
+    cache_search(key.clone(), &[result("https://example.com/cat.jpg")]);
+    assert!(cached_search(&key, ttl).is_some());
+
+    assert!(clear_search_cache() >= 1);
+    // A miss is what sends search_within back to the engines
+    assert!(cached_search(&key, ttl).is_none());
+  }
+
+  #[test]
+  fn icon_sized_results_are_skipped() {
+    assert!(is_below_min_resolution(Some(50), Some(50)));
+    assert!(!is_below_min_resolution(Some(800), Some(600)));
+    // Unknown sizes pass through
+    assert!(!is_below_min_resolution(None, None));
+  }
+
+  #[test]
+  fn blocked_domains_match_host_and_subdomains() {
+    let denylist = vec!["example.com".to_string()];
+    assert!(is_blocked_url("https://example.com/a.jpg", &denylist));
+    assert!(is_blocked_url("https://img.Example.com/a.jpg", &denylist));
+    assert!(!is_blocked_url(
+      "https://notblocked-example.com/a.jpg",
+      &denylist
+    ));
+  }
+
+  #[test]
+  fn other_entries_match_anywhere_in_the_url() {
+    let denylist: Vec<String> = DEFAULT_BLOCKED_URL_PATTERNS
+      .iter()
+      .map(|pattern| pattern.to_string())
+      .chain(["/thumbs/".to_string()])
+      .collect();
+    assert!(is_blocked_url(
+      "https://encrypted-tbn0.gstatic.com/images?q=tbn",
+      &denylist
+    ));
+    assert!(is_blocked_url("https://cdn.net/thumbs/a.jpg", &denylist));
+    assert!(!is_blocked_url("https://cdn.net/full/a.jpg", &denylist));
+  }
+
+  #[test]
+  fn only_http_urls_are_fetchable() {
+    assert!(is_fetchable_url("https://example.com/a.jpg"));
+    assert!(is_fetchable_url("http://example.com/a.jpg"));
+    assert!(!is_fetchable_url("data:image/png;base64,iVBORw0KGgo="));
+    assert!(!is_fetchable_url("javascript:alert(1)"));
+    assert!(!is_fetchable_url("file:///etc/passwd"));
+    assert!(!is_fetchable_url("not a url"));
+  }
+
+  #[test]
+  fn html_is_decoded_by_its_declared_charset() {
+    let html = decode_html("Google", Some("text/html; charset=UTF-8"), "貓".as_bytes());
+    assert_eq!(html.unwrap(), "貓");
+    let html = decode_html(
+      "Bing",
+      Some("text/html; charset=\"ISO-8859-1\""),
+      b"caf\xe9",
+    );
+    assert_eq!(html.unwrap(), "café");
+    let html = decode_html("Bing", Some("text/html; charset=Shift_JIS"), b"\x94\x4c");
+    assert_eq!(html.unwrap(), "猫");
+    assert!(decode_html("Google", Some("text/html; charset=klingon"), b"a").is_err());
+  }
+
+  #[test]
+  fn only_urls_with_invalid_bytes_are_dropped() {
+    // "貓" cut mid-character inside one URL; the other URL on the page is intact
+    let mut body = b"https://example.com/".to_vec();
+    body.extend_from_slice(&"貓".as_bytes()[..2]);
+    body.extend_from_slice(b".jpg https://example.com/cat.jpg");
+    let html = decode_html("Google", None, &body).unwrap();
+    let urls: Vec<String> = html.split(' ').map(str::to_string).collect();
+    assert!(urls[0].contains(char::REPLACEMENT_CHARACTER));
+
+    let mut combined = Vec::new();
+    merge_results(
+      SearchEngine::Google,
+      Some(Ok(urls)),
+      &mut combined,
+      &mut HashSet::new(),
+      &mut false,
+      &mut Vec::new(),
+    );
+
+    let urls: Vec<_> = combined.iter().map(|result| result.url.as_str()).collect();
+    assert_eq!(urls, ["https://example.com/cat.jpg"]);
+  }
+
+  #[test]
+  fn candidates_past_the_tenth_are_kept_as_fallbacks() {
+    let urls: Vec<String> = (1..=15)
+      .map(|n| format!("https://example.com/{}.jpg", n))
+      .collect();
+    let (mut combined, mut seen, mut had_success, mut errors) =
+      (Vec::new(), HashSet::new(), false, Vec::new());
+
+    merge_results(
+      SearchEngine::SerpApi,
+      Some(Ok(urls)),
+      &mut combined,
+      &mut seen,
+      &mut had_success,
+      &mut errors,
+    );
+
+    // With the first ten candidates dead, the send loop still has the eleventh to try
+    assert!(candidate_limit() > 10);
+    assert_eq!(combined.len(), 15.min(candidate_limit()));
+    assert_eq!(combined[10].url, "https://example.com/11.jpg");
+  }
+
+  #[tokio::test]
+  async fn deadline_returns_the_results_that_arrived() {
+    tokio::time::pause();
+    let deadline = Some(Duration::from_millis(2000));
+    let fast = within_deadline(SearchEngine::Ddg, deadline, async {
+      Ok(vec!["https://example.com/fast.jpg".to_string()])
+    })
+    .await;
+    let slow = within_deadline(
+      SearchEngine::Bing,
+      deadline,
+      std::future::pending::<std::result::Result<Vec<String>, SearchError>>(),
+    )
+    .await;
+    assert!(matches!(slow, Err(SearchError::TimedOut { .. })));
+
+    let (mut combined, mut seen, mut had_success, mut errors) =
+      (Vec::new(), HashSet::new(), false, Vec::new());
+    for (engine, result) in [(SearchEngine::Ddg, fast), (SearchEngine::Bing, slow)] {
+      merge_results(
+        engine,
+        Some(result),
+        &mut combined,
+        &mut seen,
+        &mut had_success,
+        &mut errors,
+      );
+    }
+
+    assert_eq!(combined.len(), 1);
+    assert_eq!(combined[0].url, "https://example.com/fast.jpg");
+    assert_eq!(errors.len(), 1);
+  }
+
+  #[test]
+  fn preferred_patterns_move_high_quality_urls_first() {
+    let mut results = vec![
+      result("https://cdn.example.com/a_150.jpg"),
+      result("https://cdn.example.com/b_640.jpg"),
+      result("https://cdn.example.com/c_1280.jpg"),
+      result("https://cdn.example.com/d_1280.jpg"),
+    ];
+    let patterns = vec!["_1280.".to_string(), "_640.".to_string()];
+
+    prefer_url_patterns(&mut results, &patterns);
+
+    let urls: Vec<&str> = results.iter().map(|result| result.url.as_str()).collect();
+    assert_eq!(
+      urls,
+      [
+        "https://cdn.example.com/c_1280.jpg",
+        "https://cdn.example.com/d_1280.jpg",
+        "https://cdn.example.com/b_640.jpg",
+        "https://cdn.example.com/a_150.jpg"
+      ]
+    );
+  }
+
+  #[test]
+  fn no_patterns_keep_the_original_order() {
+    let mut results = vec![result("https://a.com/1.jpg"), result("https://b.com/2.jpg")];
+    prefer_url_patterns(&mut results, &[]);
+    assert_eq!(results[0].url, "https://a.com/1.jpg");
+  }
+
+  #[tokio::test]
+  async fn message_search_is_reused_by_inline_queries() {
+    let _cache = search_cache_lock().lock().await;
+    let locale = SearchLocale::default();
+    // What the message path caches for "Mic Drop.jpg"
+    cache_search(
+      search_cache_key("Mic Drop", false, &locale),
+      &[result("https://example.com/mic-drop.jpg")],
+    );
+
+    // The inline path searches "mic  drop.jpg" under a deadline and must not scrape again
+    let results = search_within("mic  drop", false, &locale, Some(Duration::from_millis(1)))
+      .await
+      .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].url, "https://example.com/mic-drop.jpg");
+  }
+
+  #[test]
+  fn chain_is_built_from_config_names_in_order() {
+    assert_eq!(
+      build_chain(&["bing", " GOOGLE ", "bing", "ddg"]).unwrap(),
+      [SearchEngine::Bing, SearchEngine::Google, SearchEngine::Ddg]
+    );
+  }
+
+  #[test]
+  fn unknown_engine_name_is_an_error() {
+    let error = build_chain(&["google", "altavista"]).unwrap_err();
+    assert!(error.to_string().contains("altavista"));
+  }
+
+  #[test]
+  fn in_flight_searches_are_counted_until_dropped() {
+    let searches: Vec<_> = (0..3).map(|_| SearchInFlight::start()).collect();
+    assert!(searches_in_flight() >= 3);
+    let during = searches_in_flight();
+
+    drop(searches);
+
+    assert!(searches_in_flight() <= during - 3);
+  }
+
+  #[test]
+  fn very_wide_image_is_excluded_by_the_max_ratio() {
+    let bounds = (None, Some(2.0));
+    assert!(is_outside_ratio_bounds(Some(1600), Some(200), bounds));
+    assert!(!is_outside_ratio_bounds(Some(800), Some(600), bounds));
+    // Unknown dimensions pass through
+    assert!(!is_outside_ratio_bounds(Some(1600), None, bounds));
+    assert!(!is_outside_ratio_bounds(None, None, bounds));
+
+    let tall_only = (Some(0.5), None);
+    assert!(is_outside_ratio_bounds(Some(100), Some(400), tall_only));
+    assert!(!is_outside_ratio_bounds(Some(1600), Some(200), tall_only));
+  }
+}
@@ -2,35 +2,43 @@ use log::{debug, info};
 use reqwest::Client;
 use serde_json::Value;
 use std::collections::HashSet;
-use std::env;
 
-use crate::image_search::{SearchError, MAX_RESULTS};
+use crate::config_file::setting;
+use crate::image_search::{extract_limit, is_unwanted_size, SearchError, SearchLocale};
 
 const ENGINE_NAME: &str = "SerpAPI";
 const ENDPOINT: &str = "https://serpapi.com/search.json";
 
 pub fn is_configured() -> bool {
-  env::var("SERP_API")
+  setting("SERP_API")
     .map(|value| !value.trim().is_empty())
     .unwrap_or(false)
 }
 
-pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchError> {
-  let api_key = env::var("SERP_API").map_err(|_| SearchError::NetworkFailed {
+pub async fn search(
+  query: &str,
+  is_gif: bool,
+  locale: &SearchLocale,
+) -> Result<Vec<String>, SearchError> {
+  let api_key = setting("SERP_API").map_err(|_| SearchError::NetworkFailed {
     engine: ENGINE_NAME,
     details: "SERP_API is not configured".to_string(),
   })?;
 
   let image_type = if is_gif { "animated" } else { "photo" };
-  let params = [
+  let hl = locale.hl().to_lowercase();
+  let gl = locale.gl();
+  let mut params = vec![
     ("engine", "google_images"),
     ("q", query),
     ("api_key", api_key.as_str()),
-    ("hl", "zh-tw"),
-    ("gl", "tw"),
+    ("hl", hl.as_str()),
     ("google_domain", "google.com"),
     ("image_type", image_type),
   ];
+  if let Some(gl) = gl.as_deref() {
+    params.push(("gl", gl));
+  }
 
   info!(
     "Searching SerpAPI Google Images for query: '{}', is_gif: {}",
@@ -86,10 +94,17 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
 
   if let Some(results) = parsed.get("images_results").and_then(Value::as_array) {
     for result in results {
-      if urls.len() >= MAX_RESULTS {
+      if urls.len() >= extract_limit() {
         break;
       }
 
+      if is_unwanted_size(
+        result.get("original_width").and_then(Value::as_u64),
+        result.get("original_height").and_then(Value::as_u64),
+      ) {
+        continue;
+      }
+
       let candidate = result
         .get("original")
         .and_then(Value::as_str)
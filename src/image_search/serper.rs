@@ -2,21 +2,25 @@ use log::{debug, info};
 use reqwest::Client;
 use serde_json::Value;
 use std::collections::HashSet;
-use std::env;
 
-use crate::image_search::{SearchError, MAX_RESULTS};
+use crate::config_file::setting;
+use crate::image_search::{extract_limit, is_unwanted_size, SearchError, SearchLocale};
 
 const ENGINE_NAME: &str = "Serper";
 const ENDPOINT: &str = "https://google.serper.dev/images";
 
 pub fn is_configured() -> bool {
-  env::var("SERPER_API")
+  setting("SERPER_API")
     .map(|value| !value.trim().is_empty())
     .unwrap_or(false)
 }
 
-pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchError> {
-  let api_key = env::var("SERPER_API").map_err(|_| SearchError::NetworkFailed {
+pub async fn search(
+  query: &str,
+  is_gif: bool,
+  locale: &SearchLocale,
+) -> Result<Vec<String>, SearchError> {
+  let api_key = setting("SERPER_API").map_err(|_| SearchError::NetworkFailed {
     engine: ENGINE_NAME,
     details: "SERPER_API is not configured".to_string(),
   })?;
@@ -27,13 +31,15 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
   );
 
   let client = Client::new();
-  let payload = serde_json::json!({
+  let mut payload = serde_json::json!({
     "q": query,
-    "gl": "tw",
-    "hl": "zh-tw",
+    "hl": locale.hl().to_lowercase(),
     "type": if is_gif { "gif" } else { "images" }
-  })
-  .to_string();
+  });
+  if let Some(gl) = locale.gl() {
+    payload["gl"] = Value::String(gl);
+  }
+  let payload = payload.to_string();
 
   let response = client
     .post(ENDPOINT)
@@ -80,10 +86,17 @@ pub async fn search(query: &str, is_gif: bool) -> Result<Vec<String>, SearchErro
 
   if let Some(results) = parsed.get("images").and_then(Value::as_array) {
     for result in results {
-      if urls.len() >= MAX_RESULTS {
+      if urls.len() >= extract_limit() {
         break;
       }
 
+      if is_unwanted_size(
+        result.get("imageWidth").and_then(Value::as_u64),
+        result.get("imageHeight").and_then(Value::as_u64),
+      ) {
+        continue;
+      }
+
       let candidate = result
         .get("imageUrl")
         .and_then(Value::as_str)
@@ -0,0 +1,150 @@
+use log::{error, info};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::config_file::setting;
+use crate::image_search::SearchEngine;
+
+const DEFAULT_WINDOW: usize = 50;
+const DEFAULT_ALERT_PERCENT: f64 = 10.0;
+// Too few samples make a single failure look like an outage
+const MIN_SAMPLES: usize = 10;
+
+static OUTCOMES: OnceLock<Mutex<HashMap<SearchEngine, EngineOutcomes>>> = OnceLock::new();
+
+#[derive(Default)]
+struct EngineOutcomes {
+  recent: VecDeque<bool>,
+  // Set while below the threshold, so the alert fires once per drop rather than per search
+  alerting: bool,
+}
+
+// Number of recent searches per engine the rate is computed over (SUCCESS_RATE_WINDOW)
+fn window() -> usize {
+  setting("SUCCESS_RATE_WINDOW")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .filter(|window| *window > 0)
+    .unwrap_or(DEFAULT_WINDOW)
+}
+
+// Success rate in percent below which an engine is reported as broken (SUCCESS_RATE_ALERT)
+fn alert_percent() -> f64 {
+  setting("SUCCESS_RATE_ALERT")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(DEFAULT_ALERT_PERCENT)
+}
+
+// Record whether an engine's search succeeded and alert when its recent success rate collapses,
+// which usually means the engine changed its page layout or started blocking the bot
+pub fn record_outcome(engine: SearchEngine, success: bool) {
+  let window = window();
+  let threshold = alert_percent();
+  let mut outcomes = outcomes();
+  let entry = outcomes.entry(engine).or_default();
+  match entry.record(success, window, threshold) {
+    Some(Transition::Dropped) => error!(
+      "{} success rate dropped to {:.0}% over the last {} searches (alert below {}%)",
+      engine.label(),
+      percent(&entry.recent),
+      entry.recent.len(),
+      threshold
+    ),
+    Some(Transition::Recovered) => info!(
+      "{} success rate recovered to {:.0}% over the last {} searches",
+      engine.label(),
+      percent(&entry.recent),
+      entry.recent.len()
+    ),
+    None => {}
+  }
+}
+
+#[derive(Debug, PartialEq)]
+enum Transition {
+  Dropped,
+  Recovered,
+}
+
+impl EngineOutcomes {
+  // Slide the window forward and report when the rate crosses the threshold in either direction
+  fn record(&mut self, success: bool, window: usize, threshold: f64) -> Option<Transition> {
+    if self.recent.len() >= window {
+      self.recent.pop_front();
+    }
+    self.recent.push_back(success);
+
+    if self.recent.len() < MIN_SAMPLES.min(window) {
+      return None;
+    }
+    let rate = percent(&self.recent);
+    if rate < threshold && !self.alerting {
+      self.alerting = true;
+      Some(Transition::Dropped)
+    } else if rate >= threshold && self.alerting {
+      self.alerting = false;
+      Some(Transition::Recovered)
+    } else {
+      None
+    }
+  }
+}
+
+// (engine, searches in the window, success rate in percent) for every engine used so far
+pub fn success_rates() -> Vec<(SearchEngine, usize, f64)> {
+  let outcomes = outcomes();
+  SearchEngine::ALL
+    .into_iter()
+    .filter_map(|engine| {
+      let entry = outcomes.get(&engine)?;
+      Some((engine, entry.recent.len(), percent(&entry.recent)))
+    })
+    .collect()
+}
+
+fn percent(recent: &VecDeque<bool>) -> f64 {
+  if recent.is_empty() {
+    return 0.0;
+  }
+  let successes = recent.iter().filter(|success| **success).count();
+  successes as f64 * 100.0 / recent.len() as f64
+}
+
+fn outcomes() -> MutexGuard<'static, HashMap<SearchEngine, EngineOutcomes>> {
+  OUTCOMES
+    .get_or_init(|| Mutex::new(HashMap::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn collapsing_success_rate_alerts_once_and_recovers() {
+    let mut entry = EngineOutcomes::default();
+    for _ in 0..10 {
+      assert_eq!(entry.record(true, 10, 10.0), None);
+    }
+
+    // Nine failures leave 10% in the window, the tenth drops it to zero
+    let transitions: Vec<_> = (0..10)
+      .filter_map(|_| entry.record(false, 10, 10.0))
+      .collect();
+    assert_eq!(transitions, vec![Transition::Dropped]);
+    assert_eq!(percent(&entry.recent), 0.0);
+
+    assert_eq!(entry.record(true, 10, 10.0), Some(Transition::Recovered));
+  }
+
+  #[test]
+  fn no_alert_before_enough_samples() {
+    let mut entry = EngineOutcomes::default();
+    for _ in 0..MIN_SAMPLES - 1 {
+      assert_eq!(entry.record(false, 50, 10.0), None);
+    }
+    assert_eq!(entry.record(false, 50, 10.0), Some(Transition::Dropped));
+  }
+}
@@ -0,0 +1,491 @@
+use anyhow::Result;
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::Cursor;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use tokio::sync::Semaphore;
+
+use crate::config_file::setting;
+
+// Telegram rejects photos whose longest side exceeds this
+const DEFAULT_MAX_PHOTO_DIMENSION: u32 = 10000;
+// ...or whose width and height add up to more than this
+const MAX_PHOTO_SIDE_SUM: u32 = 10000;
+// ...or whose long side is more than this many times the short one
+const MAX_PHOTO_ASPECT_RATIO: u32 = 20;
+const MAX_DECODE_FAILURES: usize = 50;
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+static DECODE_FAILURES: OnceLock<Mutex<DecodeFailures>> = OnceLock::new();
+static DECODE_PERMITS: OnceLock<Semaphore> = OnceLock::new();
+
+#[derive(Default)]
+struct DecodeFailures {
+  total: usize,
+  recent: VecDeque<String>,
+}
+
+// Longest side allowed before downscaling, from MAX_PHOTO_DIMENSION (0 disables; Telegram's
+// own size limits still apply)
+pub fn max_photo_dimension() -> u32 {
+  setting("MAX_PHOTO_DIMENSION")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(DEFAULT_MAX_PHOTO_DIMENSION)
+}
+
+// JPEG quality for transcoding other formats before sending, or None unless CONVERT_TO_JPEG
+// is enabled. JPEG_QUALITY (1-100) defaults to 85.
+pub fn jpeg_conversion_quality() -> Option<u8> {
+  let enabled = setting("CONVERT_TO_JPEG")
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false);
+  enabled.then(|| {
+    setting("JPEG_QUALITY")
+      .ok()
+      .and_then(|value| value.trim().parse().ok())
+      .filter(|quality| (1..=100).contains(quality))
+      .unwrap_or(DEFAULT_JPEG_QUALITY)
+  })
+}
+
+// Whether downloaded JPEGs are re-encoded without EXIF (camera details, GPS), from STRIP_METADATA
+pub fn strip_metadata_enabled() -> bool {
+  setting("STRIP_METADATA")
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+// Re-encode a JPEG so none of its metadata segments survive. The EXIF orientation is applied
+// to the pixels first so the photo isn't shown rotated. Returns None for non-JPEG input.
+pub fn strip_jpeg_metadata(bytes: &[u8], quality: u8) -> Result<Option<Vec<u8>>, anyhow::Error> {
+  if image::guess_format(bytes)? != ImageFormat::Jpeg {
+    return Ok(None);
+  }
+
+  let mut decoder = ImageReader::new(Cursor::new(bytes))
+    .with_guessed_format()?
+    .into_decoder()?;
+  let orientation = decoder.orientation()?;
+  let mut image = DynamicImage::from_decoder(decoder)?;
+  image.apply_orientation(orientation);
+
+  let mut output = Vec::new();
+  let encoder = JpegEncoder::new_with_quality(&mut output, quality);
+  image.to_rgb8().write_with_encoder(encoder)?;
+  info!(
+    "Stripped metadata from JPEG: {} -> {} bytes",
+    bytes.len(),
+    output.len()
+  );
+  Ok(Some(output))
+}
+
+// Transcode a non-JPEG image (e.g. a large PNG) to JPEG at the given quality.
+// Returns None when the input is already a JPEG or the result wouldn't be smaller.
+pub fn convert_to_jpeg(bytes: &[u8], quality: u8) -> Result<Option<Vec<u8>>, anyhow::Error> {
+  if image::guess_format(bytes)? == ImageFormat::Jpeg {
+    return Ok(None);
+  }
+
+  let mut output = Vec::new();
+  let encoder = JpegEncoder::new_with_quality(&mut output, quality);
+  image::load_from_memory(bytes)?
+    .to_rgb8()
+    .write_with_encoder(encoder)?;
+
+  if output.len() >= bytes.len() {
+    return Ok(None);
+  }
+  info!(
+    "Converted image to JPEG: {} -> {} bytes",
+    bytes.len(),
+    output.len()
+  );
+  Ok(Some(output))
+}
+
+// Re-encode as a JPEG that Telegram accepts as a photo: the longest side fits within
+// max_dimension, the sides add up to at most MAX_PHOTO_SIDE_SUM, and images stretched past
+// MAX_PHOTO_ASPECT_RATIO are letterboxed onto a white canvas. Aspect ratio is preserved.
+// Returns None when the image already fits (only the header is read in that case).
+pub fn downscale_to_fit(
+  bytes: &[u8],
+  max_dimension: u32,
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+  let (width, height) = ImageReader::new(Cursor::new(bytes))
+    .with_guessed_format()?
+    .into_dimensions()?;
+  let (canvas_width, canvas_height) = photo_canvas(width, height, max_dimension);
+  if (canvas_width, canvas_height) == (width, height) {
+    return Ok(None);
+  }
+
+  info!(
+    "Fitting {}x{} image into a {}x{} photo",
+    width, height, canvas_width, canvas_height
+  );
+  let resized = image::load_from_memory(bytes)?
+    .resize(canvas_width, canvas_height, FilterType::Triangle)
+    .to_rgb8();
+  let fitted = if resized.dimensions() == (canvas_width, canvas_height) {
+    resized
+  } else {
+    let mut canvas =
+      image::RgbImage::from_pixel(canvas_width, canvas_height, image::Rgb([255, 255, 255]));
+    let x = (canvas_width - resized.width()) / 2;
+    let y = (canvas_height - resized.height()) / 2;
+    image::imageops::overlay(&mut canvas, &resized, x.into(), y.into());
+    canvas
+  };
+
+  let mut output = Vec::new();
+  fitted.write_to(&mut Cursor::new(&mut output), ImageFormat::Jpeg)?;
+  Ok(Some(output))
+}
+
+// Photo size for a width x height image: the short side padded up to the aspect ratio limit,
+// then everything scaled down to the dimension and side-sum limits
+fn photo_canvas(width: u32, height: u32, max_dimension: u32) -> (u32, u32) {
+  let ratio = f64::from(MAX_PHOTO_ASPECT_RATIO);
+  let (mut canvas_width, mut canvas_height) = (f64::from(width), f64::from(height));
+  if canvas_width > canvas_height * ratio {
+    canvas_height = (canvas_width / ratio).ceil();
+  } else if canvas_height > canvas_width * ratio {
+    canvas_width = (canvas_height / ratio).ceil();
+  }
+
+  let mut scale = f64::from(MAX_PHOTO_SIDE_SUM) / (canvas_width + canvas_height);
+  if max_dimension > 0 {
+    scale = scale.min(f64::from(max_dimension) / canvas_width.max(canvas_height));
+  }
+  if scale >= 1.0 {
+    return (canvas_width as u32, canvas_height as u32);
+  }
+
+  let mut scaled_width = ((canvas_width * scale).floor() as u32).max(1);
+  let mut scaled_height = ((canvas_height * scale).floor() as u32).max(1);
+  // Rounding the short side up keeps the ratio in bounds; trim the long side if that overshoots
+  if scaled_width > scaled_height * MAX_PHOTO_ASPECT_RATIO {
+    scaled_height = scaled_width.div_ceil(MAX_PHOTO_ASPECT_RATIO);
+  } else if scaled_height > scaled_width * MAX_PHOTO_ASPECT_RATIO {
+    scaled_width = scaled_height.div_ceil(MAX_PHOTO_ASPECT_RATIO);
+  }
+  let excess = (scaled_width + scaled_height).saturating_sub(MAX_PHOTO_SIDE_SUM);
+  if scaled_width >= scaled_height {
+    scaled_width -= excess;
+  } else {
+    scaled_height -= excess;
+  }
+  (scaled_width, scaled_height)
+}
+
+// How many image decode/encode jobs may run at once, from DECODE_CONCURRENCY
+// (default: one per CPU). Keeps bursts from filling tokio's blocking thread pool.
+fn decode_permits() -> &'static Semaphore {
+  DECODE_PERMITS.get_or_init(|| {
+    let permits = setting("DECODE_CONCURRENCY")
+      .ok()
+      .and_then(|value| value.trim().parse().ok())
+      .filter(|permits| *permits > 0)
+      .unwrap_or_else(|| {
+        std::thread::available_parallelism()
+          .map(|parallelism| parallelism.get())
+          .unwrap_or(1)
+      });
+    Semaphore::new(permits)
+  })
+}
+
+// Run CPU-bound image work on the blocking pool, waiting for a free decode slot first
+pub async fn run_image_job<T, F>(job: F) -> Result<T, anyhow::Error>
+where
+  F: FnOnce() -> T + Send + 'static,
+  T: Send + 'static,
+{
+  run_with_permit(decode_permits(), job).await
+}
+
+async fn run_with_permit<T, F>(permits: &Semaphore, job: F) -> Result<T, anyhow::Error>
+where
+  F: FnOnce() -> T + Send + 'static,
+  T: Send + 'static,
+{
+  let _permit = permits.acquire().await?;
+  Ok(tokio::task::spawn_blocking(job).await?)
+}
+
+// Make downloaded photo bytes acceptable to Telegram, falling back to the original on failure
+pub async fn prepare_photo(source: &str, bytes: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+  let max_dimension = max_photo_dimension();
+  let jpeg_quality = jpeg_conversion_quality();
+  let strip_metadata = strip_metadata_enabled();
+  let source = source.to_string();
+  let prepared = run_image_job(move || {
+    // Re-encoded output never carries metadata, so stripping is only needed when nothing else ran
+    let prepared = downscale_to_fit(&bytes, max_dimension)
+      .and_then(|resized| match (resized, jpeg_quality) {
+        (Some(resized), _) => Ok(Some(resized)),
+        (None, Some(quality)) => convert_to_jpeg(&bytes, quality),
+        (None, None) => Ok(None),
+      })
+      .and_then(|converted| match converted {
+        Some(converted) => Ok(Some(converted)),
+        None if strip_metadata => {
+          strip_jpeg_metadata(&bytes, jpeg_quality.unwrap_or(DEFAULT_JPEG_QUALITY))
+        }
+        None => Ok(None),
+      });
+    match prepared {
+      Ok(Some(prepared)) => prepared,
+      Ok(None) => bytes,
+      Err(e) => {
+        // Telegram may still accept what we can't decode, so send the raw bytes
+        record_decode_failure(&source, &e);
+        bytes
+      }
+    }
+  })
+  .await?;
+  Ok(prepared)
+}
+
+// Decode a GIF's header and first frame; a truncated or corrupt download fails here
+// instead of in send_animation with an unhelpful Telegram error
+pub fn validate_gif(bytes: &[u8]) -> Result<(), anyhow::Error> {
+  let decoder = GifDecoder::new(Cursor::new(bytes))?;
+  match decoder.into_frames().next() {
+    Some(frame) => {
+      frame?;
+      Ok(())
+    }
+    None => Err(anyhow::anyhow!("GIF has no frames")),
+  }
+}
+
+// Reject downloaded GIFs that don't decode so the caller moves on to the next candidate.
+// Anything that isn't a GIF is passed through untouched.
+pub async fn ensure_complete_gif(source: &str, bytes: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+  if image::guess_format(&bytes).ok() != Some(ImageFormat::Gif) {
+    return Ok(bytes);
+  }
+
+  let (bytes, checked) = run_image_job(move || {
+    let checked = validate_gif(&bytes);
+    (bytes, checked)
+  })
+  .await?;
+  if let Err(e) = checked {
+    record_decode_failure(source, &e);
+    return Err(e.context("downloaded GIF is truncated or corrupt"));
+  }
+  Ok(bytes)
+}
+
+// Note an image that couldn't be decoded. Each source is logged once while it stays
+// among the last MAX_DECODE_FAILURES; callers decide how to degrade (skip or send raw).
+pub fn record_decode_failure(source: &str, error: &dyn Display) {
+  let mut failures = decode_failures_guard();
+  if failures.recent.iter().any(|known| known == source) {
+    return;
+  }
+
+  warn!("Could not decode image {}: {}", source, error);
+  failures.total += 1;
+  if failures.recent.len() >= MAX_DECODE_FAILURES {
+    failures.recent.pop_front();
+  }
+  failures.recent.push_back(source.to_string());
+}
+
+// Total undecodable sources seen since startup, and the most recent ones (newest last)
+pub fn decode_failures() -> (usize, Vec<String>) {
+  let failures = decode_failures_guard();
+  (failures.total, failures.recent.iter().cloned().collect())
+}
+
+fn decode_failures_guard() -> MutexGuard<'static, DecodeFailures> {
+  DECODE_FAILURES
+    .get_or_init(|| Mutex::new(DecodeFailures::default()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn jpeg_bytes(width: u32, height: u32) -> Vec<u8> {
+    let mut output = Vec::new();
+    DynamicImage::new_rgb8(width, height)
+      .write_to(&mut Cursor::new(&mut output), ImageFormat::Jpeg)
+      .unwrap();
+    output
+  }
+
+  #[tokio::test]
+  async fn truncated_jpeg_is_recorded_and_sent_raw() {
+    // Cut off before the frame header, so not even the dimensions can be read
+    let truncated = jpeg_bytes(64, 64)[..30].to_vec();
+    let source = "https://example.com/truncated.jpg";
+
+    let prepared = prepare_photo(source, truncated.clone()).await.unwrap();
+
+    assert_eq!(prepared, truncated);
+    let (total, recent) = decode_failures();
+    assert!(total >= 1);
+    assert!(recent.iter().any(|failed| failed == source));
+  }
+
+  #[test]
+  fn oversized_image_is_scaled_down_keeping_aspect_ratio() {
+    let oversized = jpeg_bytes(300, 60);
+
+    let resized = downscale_to_fit(&oversized, 100).unwrap().unwrap();
+
+    let resized = image::load_from_memory(&resized).unwrap();
+    assert_eq!((resized.width(), resized.height()), (100, 20));
+    assert_eq!(downscale_to_fit(&oversized, 300).unwrap(), None);
+  }
+
+  #[test]
+  fn png_converts_to_a_smaller_jpeg() {
+    // Noise keeps PNG compression from winning
+    let mut seed: u32 = 698;
+    let png = image::RgbImage::from_fn(128, 128, |_, _| {
+      seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+      let [_, r, g, b] = seed.to_le_bytes();
+      image::Rgb([r, g, b])
+    });
+    let mut png_bytes = Vec::new();
+    DynamicImage::ImageRgb8(png)
+      .write_to(&mut Cursor::new(&mut png_bytes), ImageFormat::Png)
+      .unwrap();
+
+    let jpeg = convert_to_jpeg(&png_bytes, 50).unwrap().unwrap();
+
+    assert!(jpeg.len() < png_bytes.len());
+    assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+    // JPEGs are left alone
+    assert_eq!(convert_to_jpeg(&jpeg_bytes(16, 16), 80).unwrap(), None);
+  }
+
+  // Markers of the segments before the image data; JPEG metadata lives in APPn segments
+  fn segment_markers(jpeg: &[u8]) -> Vec<u8> {
+    let mut markers = Vec::new();
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() && jpeg[pos] == 0xFF {
+      let marker = jpeg[pos + 1];
+      markers.push(marker);
+      if marker == 0xDA {
+        break;
+      }
+      pos += 2 + u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+    }
+    markers
+  }
+
+  #[test]
+  fn stripped_jpeg_has_no_exif_segment() {
+    // Empty big-endian TIFF directory, enough for decoders to accept the EXIF block
+    let tiff = [b'M', b'M', 0, 42, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0];
+    let mut exif = vec![0xFF, 0xE1];
+    exif.extend_from_slice(&(2 + 6 + tiff.len() as u16).to_be_bytes());
+    exif.extend_from_slice(b"Exif\0\0");
+    exif.extend_from_slice(&tiff);
+    let mut tagged = jpeg_bytes(32, 32);
+    tagged.splice(2..2, exif);
+    assert!(segment_markers(&tagged).contains(&0xE1));
+
+    let stripped = strip_jpeg_metadata(&tagged, 90).unwrap().unwrap();
+
+    assert!(!segment_markers(&stripped).contains(&0xE1));
+    assert!(!stripped.windows(6).any(|window| window == b"Exif\0\0"));
+    let decoded = image::load_from_memory(&stripped).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (32, 32));
+  }
+
+  #[tokio::test]
+  async fn concurrent_image_jobs_are_capped() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let permits = Arc::new(Semaphore::new(2));
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let jobs: Vec<_> = (0..6)
+      .map(|_| {
+        let (permits, running, peak) = (permits.clone(), running.clone(), peak.clone());
+        tokio::spawn(async move {
+          run_with_permit(&permits, move || {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+          })
+          .await
+        })
+      })
+      .collect();
+    for job in jobs {
+      job.await.unwrap().unwrap();
+    }
+
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+  }
+
+  #[tokio::test]
+  async fn truncated_gif_is_rejected_before_sending() {
+    // Noise keeps the LZW data long enough to cut through the middle of the frame
+    let mut seed: u32 = 741;
+    let frame = image::RgbaImage::from_fn(64, 64, |_, _| {
+      seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+      let [_, r, g, b] = seed.to_le_bytes();
+      image::Rgba([r, g, b, 255])
+    });
+    let mut gif = Vec::new();
+    DynamicImage::ImageRgba8(frame)
+      .write_to(&mut Cursor::new(&mut gif), ImageFormat::Gif)
+      .unwrap();
+    let truncated = gif[..gif.len() / 2].to_vec();
+    let source = "https://example.com/truncated.gif";
+
+    assert!(ensure_complete_gif(source, truncated).await.is_err());
+    let (_, recent) = decode_failures();
+    assert!(recent.iter().any(|failed| failed == source));
+
+    assert_eq!(
+      ensure_complete_gif("https://example.com/whole.gif", gif.clone())
+        .await
+        .unwrap(),
+      gif
+    );
+  }
+
+  #[test]
+  fn large_square_fits_the_side_sum_limit() {
+    // Both sides are under the 10000px cap, but together they exceed it
+    assert_eq!(photo_canvas(9000, 9000, 10000), (5000, 5000));
+    assert_eq!(photo_canvas(9000, 1000, 10000), (9000, 1000));
+  }
+
+  #[test]
+  fn tall_strip_is_letterboxed_to_the_ratio_limit() {
+    assert_eq!(photo_canvas(200, 8000, 10000), (400, 8000));
+    assert_eq!(photo_canvas(200, 8000, 4000), (200, 4000));
+
+    let fitted = downscale_to_fit(&jpeg_bytes(200, 8000), 10000)
+      .unwrap()
+      .unwrap();
+
+    let fitted = image::load_from_memory(&fitted).unwrap();
+    assert_eq!((fitted.width(), fitted.height()), (400, 8000));
+    // The strip sits in the middle, with white bars either side
+    assert_eq!(fitted.to_rgb8().get_pixel(0, 4000).0, [255, 255, 255]);
+    assert!(fitted.to_rgb8().get_pixel(200, 4000).0[0] < 16);
+  }
+}
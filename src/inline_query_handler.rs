@@ -1,5 +1,8 @@
 use anyhow::Result;
 use log::{error, info};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
 use teloxide::prelude::*;
 use teloxide::types::{
   ChosenInlineResult, InlineQuery, InlineQueryResult, InlineQueryResultArticle,
@@ -8,13 +11,30 @@ use teloxide::types::{
 use url::Url;
 use uuid::Uuid;
 
-use crate::image_search::search as image_search;
+use crate::banned_words::{banned_words, find_banned_word};
+use crate::chat_access::is_inline_user_allowed;
+use crate::config_file::setting;
+use crate::featured::{featured, find_featured, pin_first};
+use crate::image_search::{search_within, SearchLocale};
+use crate::maintenance::maintenance_enabled;
+use crate::response_times::{record_duration, Branch};
+use crate::send_gate;
+use crate::stopwords::{stopwords, strip_stopwords};
+
+// Telegram rejects inline result IDs longer than 64 bytes
+const MAX_INLINE_RESULT_ID_BYTES: usize = 64;
+// Telegram drops inline answers that arrive late, so slow engines are cut off
+const DEFAULT_INLINE_DEADLINE_MS: u64 = 2000;
+const DEFAULT_INLINE_PAGE_SIZE: usize = 10;
+// Telegram accepts at most 50 results per inline answer
+const MAX_INLINE_PAGE_SIZE: usize = 50;
 
 // Handle inline queries
 pub async fn handle_inline_query(bot: Bot, q: InlineQuery) -> Result<(), anyhow::Error> {
   let query = q.query.trim().to_string();
 
-  if query.is_empty() {
+  if query.is_empty() || maintenance_enabled() || !is_inline_user_allowed(q.from.id) {
+    send_gate::acquire().await;
     bot
       .answer_inline_query(&q.id, Vec::<InlineQueryResult>::new())
       .await?;
@@ -22,52 +42,45 @@ pub async fn handle_inline_query(bot: Bot, q: InlineQuery) -> Result<(), anyhow:
   }
 
   info!("Received inline query: {}", query);
+  let started = Instant::now();
+  let locale = SearchLocale::resolve(q.from.language_code.as_deref());
 
-  // Detect if the user wants GIFs (query ends with .gif)
-  let (search_query, is_gif) = if query.to_lowercase().ends_with(".gif") {
-    (
-      query
-        .trim_end_matches(|c: char| c == '.' || c.is_alphabetic())
-        .trim()
-        .to_string(),
-      true,
-    )
-  } else {
-    (
-      query
-        .trim_end_matches(|c: char| {
-          c == '.'
-            || c.is_alphabetic() && query.to_lowercase().ends_with(".jpg")
-            || c.is_alphabetic() && query.to_lowercase().ends_with(".png")
-        })
-        .trim()
-        .to_string(),
-      false,
-    )
+  // Banned queries are never searched and are answered like an empty query
+  let Some((search_query, is_gif)) = inline_search_terms(&query, stopwords())
+    .filter(|(search_query, _)| find_banned_word(search_query, banned_words()).is_none())
+  else {
+    send_gate::acquire().await;
+    bot
+      .answer_inline_query(&q.id, Vec::<InlineQueryResult>::new())
+      .await?;
+    return Ok(());
   };
 
-  // Strip common image extensions from the search query
-  let search_query = search_query
-    .trim_end_matches(".jpg")
-    .trim_end_matches(".jpeg")
-    .trim_end_matches(".png")
-    .trim_end_matches(".gif")
-    .trim()
-    .to_string();
-  let search_query = if search_query.is_empty() {
-    query.clone()
+  let deadline = inline_deadline();
+  let search = if inline_placeholder_enabled() {
+    // Keep searching past the deadline so the complete results land in the search cache
+    let background = tokio::spawn({
+      let (search_query, locale) = (search_query.clone(), locale.clone());
+      async move { search_within(&search_query, is_gif, &locale, None).await }
+    });
+    match tokio::time::timeout(deadline, background).await {
+      Ok(joined) => joined?,
+      Err(_) => {
+        answer_placeholder(&bot, &q.id, &search_query).await;
+        return Ok(());
+      }
+    }
   } else {
-    search_query
+    search_within(&search_query, is_gif, &locale, Some(deadline)).await
   };
-
-  let image_urls = match image_search(&search_query, is_gif).await {
-    Ok(urls) => {
+  let mut image_urls: Vec<String> = match search {
+    Ok(results) => {
       info!(
         "Found {} image URLs for query: {}",
-        urls.len(),
+        results.len(),
         search_query
       );
-      urls
+      results.into_iter().map(|result| result.url).collect()
     }
     Err(e) => {
       error!("Error searching images: {:?}", e);
@@ -75,57 +88,27 @@ pub async fn handle_inline_query(bot: Bot, q: InlineQuery) -> Result<(), anyhow:
     }
   };
 
-  let mut results = Vec::new();
-
-  for url_str in image_urls.iter().take(10) {
-    let parsed_url = match Url::parse(url_str) {
-      Ok(url) => url,
-      Err(e) => {
-        error!("Failed to parse URL {}: {:?}", url_str, e);
-        continue;
-      }
-    };
-
-    let id = Uuid::new_v4().to_string();
-
-    if is_gif {
-      results.push(InlineQueryResult::Gif(InlineQueryResultGif {
-        id,
-        gif_url: parsed_url.clone(),
-        thumbnail_url: parsed_url,
-        gif_width: None,
-        gif_height: None,
-        gif_duration: None,
-        thumbnail_mime_type: None,
-        title: None,
-        caption: None,
-        parse_mode: None,
-        caption_entities: None,
-        reply_markup: None,
-        input_message_content: None,
-      }));
-    } else {
-      results.push(InlineQueryResult::Photo(InlineQueryResultPhoto {
-        id,
-        photo_url: parsed_url.clone(),
-        thumbnail_url: parsed_url,
-        photo_width: None,
-        photo_height: None,
-        title: None,
-        description: None,
-        caption: None,
-        parse_mode: None,
-        caption_entities: None,
-        reply_markup: None,
-        input_message_content: None,
-      }));
-    }
+  if let Some(featured_url) = find_featured(&search_query, featured()) {
+    pin_first(&mut image_urls, featured_url);
   }
+  // Engines don't always honor the type filter, and a pin may be either kind
+  image_urls.retain(|url| matches_requested_type(url, is_gif));
 
-  if results.is_empty() {
+  let caption = inline_caption(&search_query, inline_caption_enabled());
+  let (cursor, page_size) = parse_inline_offset(&q.offset);
+  let next_offset = next_inline_offset(cursor, page_size, image_urls.len());
+  let mut results: Vec<InlineQueryResult> = image_urls
+    .iter()
+    .skip(cursor)
+    .take(page_size)
+    .filter_map(|url_str| image_result(url_str, is_gif, caption.clone()))
+    .collect();
+
+  // Later pages just end; the "no images" article only makes sense as the first answer
+  if results.is_empty() && cursor == 0 {
     info!("No results found for query: {}", query);
     results.push(InlineQueryResult::Article(InlineQueryResultArticle {
-      id: Uuid::new_v4().to_string(),
+      id: inline_result_id("none"),
       title: "No images found".to_string(),
       input_message_content: InputMessageContent::Text(InputMessageContentText {
         message_text: format!("No images found for \"{}\"", query),
@@ -144,18 +127,255 @@ pub async fn handle_inline_query(bot: Bot, q: InlineQuery) -> Result<(), anyhow:
   }
 
   info!("Answering inline query with {} results", results.len());
+  send_gate::acquire().await;
   match bot
     .answer_inline_query(&q.id, results)
     .cache_time(300)
+    .next_offset(next_offset)
     .await
   {
     Ok(_) => info!("Successfully answered inline query: {}", q.id),
     Err(e) => error!("Failed to answer inline query: {:?}", e),
   }
+  info!(
+    "[timing] inline query '{}' took {} ms",
+    query,
+    started.elapsed().as_millis()
+  );
+  record_duration(Branch::Inline, started.elapsed());
 
   Ok(())
 }
 
+// A photo or GIF result for an image URL, or None when the URL doesn't parse
+fn image_result(url_str: &str, is_gif: bool, caption: Option<String>) -> Option<InlineQueryResult> {
+  let parsed_url = match Url::parse(url_str) {
+    Ok(url) => url,
+    Err(e) => {
+      error!("Failed to parse URL {}: {:?}", url_str, e);
+      return None;
+    }
+  };
+
+  let id = url_result_id(if is_gif { "gif" } else { "photo" }, url_str);
+
+  if is_gif {
+    let thumbnail_mime_type = thumbnail_mime_type(&parsed_url).parse().ok();
+    Some(InlineQueryResult::Gif(InlineQueryResultGif {
+      id,
+      gif_url: parsed_url.clone(),
+      thumbnail_url: parsed_url,
+      gif_width: None,
+      gif_height: None,
+      gif_duration: None,
+      thumbnail_mime_type,
+      title: None,
+      caption,
+      parse_mode: None,
+      caption_entities: None,
+      reply_markup: None,
+      input_message_content: None,
+    }))
+  } else {
+    Some(InlineQueryResult::Photo(InlineQueryResultPhoto {
+      id,
+      photo_url: parsed_url.clone(),
+      thumbnail_url: parsed_url,
+      photo_width: None,
+      photo_height: None,
+      title: None,
+      description: None,
+      caption,
+      parse_mode: None,
+      caption_entities: None,
+      reply_markup: None,
+      input_message_content: None,
+    }))
+  }
+}
+
+// What to search for and whether GIFs were asked for; None when nothing is left once the
+// type suffix and stopwords are removed (e.g. a bare ".gif" or only spaces)
+fn inline_search_terms(query: &str, stopwords: &[String]) -> Option<(String, bool)> {
+  let (search_query, is_gif) = split_type_suffix(query.trim());
+  let search_query = strip_stopwords(&search_query, stopwords);
+  (!search_query.trim().is_empty()).then_some((search_query, is_gif))
+}
+
+// Mirror the message trigger: a trailing ".gif" asks for GIFs, ".jpg", ".jpeg" or ".png" for
+// photos. Without a suffix the whole query is a photo search; a bare suffix leaves it empty.
+fn split_type_suffix(query: &str) -> (String, bool) {
+  for (suffix, is_gif) in [
+    (".gif", true),
+    (".jpg", false),
+    (".jpeg", false),
+    (".png", false),
+  ] {
+    let Some(split) = query.len().checked_sub(suffix.len()) else {
+      continue;
+    };
+    let stem = query.get(..split).map(str::trim);
+    let ending = query.get(split..);
+    if let (Some(stem), Some(ending)) = (stem, ending) {
+      if ending.eq_ignore_ascii_case(suffix) {
+        return (stem.to_string(), is_gif);
+      }
+    }
+  }
+  (query.to_string(), false)
+}
+
+// Inline offsets are "<cursor>:<page size>", so every page of a query keeps the size the
+// first answer used. An empty or malformed offset starts over at the default page size.
+fn parse_inline_offset(offset: &str) -> (usize, usize) {
+  let parsed = offset.split_once(':').and_then(|(cursor, page_size)| {
+    let cursor = cursor.trim().parse().ok()?;
+    let page_size = page_size.trim().parse().ok()?;
+    Some((cursor, page_size))
+  });
+  match parsed {
+    Some((cursor, page_size)) if (1..=MAX_INLINE_PAGE_SIZE).contains(&page_size) => {
+      (cursor, page_size)
+    }
+    _ => (0, DEFAULT_INLINE_PAGE_SIZE),
+  }
+}
+
+// Offset of the page after this one, or empty once the results run out
+fn next_inline_offset(cursor: usize, page_size: usize, total: usize) -> String {
+  if cursor + page_size < total {
+    format!("{}:{}", cursor + page_size, page_size)
+  } else {
+    String::new()
+  }
+}
+
+// Whether a candidate URL fits the requested kind; URLs without a telling extension are kept
+fn matches_requested_type(url: &str, is_gif: bool) -> bool {
+  let Ok(parsed) = Url::parse(url) else {
+    return true;
+  };
+  let path = parsed.path().to_lowercase();
+  let is_still = [".jpg", ".jpeg", ".png", ".webp"]
+    .iter()
+    .any(|extension| path.ends_with(extension));
+  if is_gif {
+    !is_still
+  } else {
+    !path.ends_with(".gif")
+  }
+}
+
+// Telegram accepts a single answer per inline query, so the placeholder can't be replaced by the
+// real results. It is never cached, so the user's next keystroke asks again and is then served
+// from the search cache the background search fills.
+async fn answer_placeholder(bot: &Bot, query_id: &str, search_query: &str) {
+  info!(
+    "Search for '{}' is still running; answering with a placeholder",
+    search_query
+  );
+  send_gate::acquire().await;
+  if let Err(e) = bot
+    .answer_inline_query(query_id, vec![placeholder_result(search_query)])
+    .cache_time(0)
+    .await
+  {
+    error!("Failed to answer inline query with placeholder: {:?}", e);
+  }
+}
+
+// Single "Searching…" entry; choosing it still posts the query as a message trigger
+fn placeholder_result(search_query: &str) -> InlineQueryResult {
+  InlineQueryResult::Article(InlineQueryResultArticle {
+    id: inline_result_id("searching"),
+    title: "Searching…".to_string(),
+    input_message_content: InputMessageContent::Text(InputMessageContentText {
+      message_text: format!("{}.jpg", search_query),
+      parse_mode: None,
+      entities: None,
+      link_preview_options: None,
+    }),
+    reply_markup: None,
+    url: None,
+    hide_url: None,
+    description: Some("Still looking for images, type again in a moment".to_string()),
+    thumbnail_url: None,
+    thumbnail_width: None,
+    thumbnail_height: None,
+  })
+}
+
+// Whether slow inline searches are answered with a placeholder (INLINE_PLACEHOLDER) instead of
+// whatever arrived before the deadline
+fn inline_placeholder_enabled() -> bool {
+  setting("INLINE_PLACEHOLDER")
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+// Unique inline result ID of the form "<tag>:<uuid>", for entries that aren't images
+fn inline_result_id(tag: &str) -> String {
+  tagged_result_id(tag, Uuid::new_v4().simple().to_string())
+}
+
+// Inline result ID for an image, "<tag>:<hash of the URL>": the same URL always gets the
+// same ID, however long the URL is
+fn url_result_id(tag: &str, url: &str) -> String {
+  let mut hasher = DefaultHasher::new();
+  url.hash(&mut hasher);
+  tagged_result_id(tag, format!("{:016x}", hasher.finish()))
+}
+
+// "<tag>:<unique>", with the tag cut short so the whole ID always fits Telegram's 64-byte limit
+fn tagged_result_id(tag: &str, unique: String) -> String {
+  let room = MAX_INLINE_RESULT_ID_BYTES - unique.len() - 1;
+  let mut end = tag.len().min(room);
+  while !tag.is_char_boundary(end) {
+    end -= 1;
+  }
+
+  let id = if end == 0 {
+    unique
+  } else {
+    format!("{}:{}", &tag[..end], unique)
+  };
+  debug_assert!(id.len() <= MAX_INLINE_RESULT_ID_BYTES);
+  id
+}
+
+// GIF search results aren't always GIFs, so tell Telegram what the thumbnail URL really is
+fn thumbnail_mime_type(thumbnail_url: &Url) -> &'static str {
+  let path = thumbnail_url.path().to_lowercase();
+  if path.ends_with(".jpg") || path.ends_with(".jpeg") {
+    "image/jpeg"
+  } else if path.ends_with(".mp4") {
+    "video/mp4"
+  } else {
+    "image/gif"
+  }
+}
+
+// Time the engines get before answering with what has arrived, from INLINE_DEADLINE_MS
+fn inline_deadline() -> Duration {
+  let millis = setting("INLINE_DEADLINE_MS")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .filter(|millis| *millis > 0)
+    .unwrap_or(DEFAULT_INLINE_DEADLINE_MS);
+  Duration::from_millis(millis)
+}
+
+// Whether images sent from inline results are captioned with the query (INLINE_CAPTION_QUERY)
+fn inline_caption_enabled() -> bool {
+  setting("INLINE_CAPTION_QUERY")
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+fn inline_caption(query: &str, enabled: bool) -> Option<String> {
+  enabled.then(|| query.to_string())
+}
+
 // Handle chosen inline results
 pub async fn handle_chosen_inline_result(
   _bot: Bot,
@@ -164,3 +384,146 @@ pub async fn handle_chosen_inline_result(
   info!("Chosen inline result: {:?}", r);
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn url_result_id_is_stable_per_url() {
+    let url = "https://example.com/cat.jpg";
+    assert_eq!(url_result_id("photo", url), url_result_id("photo", url));
+    assert_ne!(
+      url_result_id("photo", url),
+      url_result_id("photo", "https://example.com/dog.jpg")
+    );
+  }
+
+  #[test]
+  fn result_ids_fit_telegrams_limit() {
+    let long_url = format!("https://example.com/{}.jpg", "a".repeat(500));
+    assert!(url_result_id("photo", &long_url).len() <= MAX_INLINE_RESULT_ID_BYTES);
+    assert!(url_result_id(&"貓".repeat(40), &long_url).len() <= MAX_INLINE_RESULT_ID_BYTES);
+    assert!(inline_result_id(&"t".repeat(100)).len() <= MAX_INLINE_RESULT_ID_BYTES);
+  }
+
+  #[test]
+  fn inline_results_carry_the_query_caption() {
+    assert_eq!(inline_caption("cat", false), None);
+    let caption = inline_caption("cat", true);
+    assert_eq!(caption.as_deref(), Some("cat"));
+
+    match image_result("https://example.com/cat.jpg", false, caption.clone()) {
+      Some(InlineQueryResult::Photo(photo)) => assert_eq!(photo.caption.as_deref(), Some("cat")),
+      other => panic!("expected a photo result, got {:?}", other),
+    }
+    match image_result("https://example.com/cat.gif", true, caption) {
+      Some(InlineQueryResult::Gif(gif)) => assert_eq!(gif.caption.as_deref(), Some("cat")),
+      other => panic!("expected a GIF result, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn thumbnail_mime_type_follows_the_thumbnail_source() {
+    let jpeg = Url::parse("https://example.com/preview.JPG").unwrap();
+    let gif = Url::parse("https://example.com/dance.gif").unwrap();
+    assert_eq!(thumbnail_mime_type(&jpeg), "image/jpeg");
+    assert_eq!(thumbnail_mime_type(&gif), "image/gif");
+
+    match image_result("https://example.com/preview.jpg", true, None) {
+      Some(InlineQueryResult::Gif(result)) => {
+        assert_eq!(result.thumbnail_mime_type.as_deref(), Some("image/jpeg"))
+      }
+      other => panic!("expected a GIF result, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn gif_suffix_offers_only_gif_results() {
+    let (search_query, is_gif) = split_type_suffix("dancing cat.GIF");
+    assert_eq!((search_query.as_str(), is_gif), ("dancing cat", true));
+    assert_eq!(split_type_suffix("cat.jpeg"), ("cat".to_string(), false));
+
+    let mut urls = vec![
+      "https://example.com/dance.gif".to_string(),
+      "https://example.com/still.jpg".to_string(),
+      "https://example.com/render?id=7".to_string(),
+      "https://example.com/still.webp".to_string(),
+    ];
+    urls.retain(|url| matches_requested_type(url, is_gif));
+    assert_eq!(
+      urls,
+      [
+        "https://example.com/dance.gif",
+        "https://example.com/render?id=7"
+      ]
+    );
+
+    let results: Vec<_> = urls
+      .iter()
+      .filter_map(|url| image_result(url, is_gif, None))
+      .collect();
+    assert_eq!(results.len(), 2);
+    assert!(results
+      .iter()
+      .all(|result| matches!(result, InlineQueryResult::Gif(_))));
+  }
+
+  #[test]
+  fn placeholder_is_a_searching_article() {
+    match placeholder_result("cat") {
+      InlineQueryResult::Article(article) => {
+        assert_eq!(article.title, "Searching…");
+        assert!(article.id.starts_with("searching:"));
+        match article.input_message_content {
+          InputMessageContent::Text(content) => assert_eq!(content.message_text, "cat.jpg"),
+          other => panic!("expected text content, got {:?}", other),
+        }
+      }
+      other => panic!("expected an article, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn blank_queries_are_handled_like_empty_ones() {
+    let stopwords = vec!["please".to_string()];
+    assert_eq!(inline_search_terms("   ", &stopwords), None);
+    assert_eq!(inline_search_terms(" .GIF ", &stopwords), None);
+    assert_eq!(inline_search_terms("  .jpg", &stopwords), None);
+    assert_eq!(
+      inline_search_terms("  please cat.gif ", &stopwords),
+      Some(("cat".to_string(), true))
+    );
+  }
+
+  #[test]
+  fn offsets_round_trip_across_pages() {
+    let total = 23;
+    let mut offset = String::new();
+    let mut pages = Vec::new();
+    loop {
+      let (cursor, page_size) = parse_inline_offset(&offset);
+      pages.push((cursor, page_size));
+      offset = next_inline_offset(cursor, page_size, total);
+      if offset.is_empty() {
+        break;
+      }
+    }
+    assert_eq!(pages, [(0, 10), (10, 10), (20, 10)]);
+
+    assert_eq!(parse_inline_offset("14:7"), (14, 7));
+    assert_eq!(next_inline_offset(14, 7, total), "21:7");
+  }
+
+  #[test]
+  fn malformed_offsets_start_over() {
+    for offset in ["", "abc", "5", "5:", "5:0", "-1:10", "5:1000"] {
+      assert_eq!(
+        parse_inline_offset(offset),
+        (0, DEFAULT_INLINE_PAGE_SIZE),
+        "{}",
+        offset
+      );
+    }
+  }
+}
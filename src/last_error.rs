@@ -0,0 +1,32 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use teloxide::types::ChatId;
+
+const LAST_ERROR_CAPACITY: usize = 1000;
+static LAST_ERRORS: OnceLock<Mutex<VecDeque<(ChatId, Instant, String)>>> = OnceLock::new();
+
+// Remember the most recent failure in a chat for /debug_last, replacing any older one
+pub fn record_error(chat_id: ChatId, detail: String) {
+  let mut errors = last_errors();
+  errors.retain(|(chat, _, _)| *chat != chat_id);
+  if errors.len() >= LAST_ERROR_CAPACITY {
+    errors.pop_front();
+  }
+  errors.push_back((chat_id, Instant::now(), detail));
+}
+
+// The chat's most recent failure and how long ago it happened
+pub fn last_error(chat_id: ChatId) -> Option<(Duration, String)> {
+  last_errors()
+    .iter()
+    .find(|(chat, _, _)| *chat == chat_id)
+    .map(|(_, at, detail)| (at.elapsed(), detail.clone()))
+}
+
+fn last_errors() -> MutexGuard<'static, VecDeque<(ChatId, Instant, String)>> {
+  LAST_ERRORS
+    .get_or_init(|| Mutex::new(VecDeque::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
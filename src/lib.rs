@@ -1,3 +1,27 @@
+pub mod banned_words;
+pub mod bot_info;
+pub mod chat_access;
+pub mod chat_settings;
+pub mod circuit_breaker;
+pub mod config_file;
+pub mod dialogue;
+pub mod download_handler;
+pub mod featured;
+pub mod feedback_handler;
+pub mod file_id_cache;
 pub mod image_search;
-pub mod imgur_handler;
+pub mod image_tools;
 pub mod inline_query_handler;
+pub mod last_error;
+pub mod maintenance;
+pub mod messages;
+pub mod missing_rights;
+pub mod montage;
+pub mod reaction_trigger;
+pub mod response_times;
+pub mod send_gate;
+pub mod send_method;
+pub mod shutdown;
+pub mod stopwords;
+pub mod unreachable_chat;
+pub mod user_throttle;
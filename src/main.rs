@@ -1,128 +1,2299 @@
-use anyhow::Result;
-use log::{error, info};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
 use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use teloxide::dispatching::{ShutdownToken, UpdateHandler};
 use teloxide::prelude::*;
-use teloxide::types::InputFile;
-use tgjpg_rs::image_search::{initialize as initialize_image_search, search as image_search};
-use tgjpg_rs::imgur_handler::{download_imgur_image, is_imgur_url};
+use teloxide::requests::HasPayload;
+use teloxide::types::{
+  InputFile, InputMedia, InputMediaPhoto, LinkPreviewOptions, MessageEntityKind, MessageId,
+  MessageReactionUpdated, ReplyParameters, User,
+};
+use tgjpg_rs::banned_words::{banned_words, find_banned_word};
+use tgjpg_rs::bot_info::BotInfo;
+use tgjpg_rs::chat_access::{is_admin, is_update_allowed};
+use tgjpg_rs::chat_settings::{
+  aliases, hashtags_enabled, load_aliases, query_caption_enabled, remove_alias, reply_target,
+  resolve_alias, result_order, save_aliases, set_alias, set_hashtags_enabled,
+  set_query_caption_enabled, set_reply_enabled, set_result_order, ResultOrder,
+  MAX_ALIASES_PER_CHAT,
+};
+use tgjpg_rs::circuit_breaker::{
+  allow_online, circuit_state, record_online_failure, record_online_success, Circuit,
+};
+use tgjpg_rs::config_file::{load_config_file, reload_config_file, setting};
+use tgjpg_rs::dialogue::{self, ChatState, ChatStorage};
+use tgjpg_rs::download_handler::{
+  clear_download_cache, download_domains, download_image, should_download,
+};
+use tgjpg_rs::featured::{featured, find_featured};
+use tgjpg_rs::feedback_handler::forward_feedback;
+use tgjpg_rs::file_id_cache::{
+  cached_file_id, forget_file_id, is_stale_file_id, remember_file_id, sent_file_id,
+};
+use tgjpg_rs::image_search::success_rate::success_rates;
+use tgjpg_rs::image_search::{
+  clear_search_cache, initialize as initialize_image_search, search as image_search,
+  searches_in_flight, SearchLocale, SearchResult,
+};
+use tgjpg_rs::image_tools::{decode_failures, ensure_complete_gif, prepare_photo};
 use tgjpg_rs::inline_query_handler::{handle_chosen_inline_result, handle_inline_query};
+use tgjpg_rs::last_error::{last_error, record_error};
+use tgjpg_rs::maintenance::{maintenance_enabled, set_maintenance};
+use tgjpg_rs::messages::{help_message, welcome_message, MessageLocale};
+use tgjpg_rs::missing_rights::{first_notice, is_missing_rights};
+use tgjpg_rs::montage::{build_montage_blocking, MAX_GRID_IMAGES};
+use tgjpg_rs::reaction_trigger::{
+  is_trigger_reaction, is_trigger_reply, recall_text, remember_text, trigger_emoji,
+};
+use tgjpg_rs::response_times::{describe_response_times, record_duration, Branch};
+use tgjpg_rs::send_gate;
+use tgjpg_rs::send_method::{send_method_for, send_method_overrides, SendMethod};
+use tgjpg_rs::shutdown::{drain, drain_timeout, in_flight, InFlight};
+use tgjpg_rs::stopwords::{stopwords, strip_stopwords};
+use tgjpg_rs::unreachable_chat::is_chat_unreachable;
+use tgjpg_rs::user_throttle::allow_search;
 use url::Url;
 
+const DEFAULT_HANDLER_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_GRID_SIZE: usize = 4;
+const MAX_QUERY_CHARS: usize = 200;
+const MAX_GIF_COUNT: usize = 5;
+// Telegram media groups hold at most 10 items
+const MAX_ALBUM_SIZE: usize = 10;
+// Telegram caps messages at 4096 characters; leave room for the header line
+const MAX_PREVIEW_CHARS: usize = 3800;
+const EDIT_MAX_AGE_SECS: i64 = 10;
+const RESPONDED_CAPACITY: usize = 1000;
+static RESPONDED_MESSAGES: OnceLock<Mutex<VecDeque<(ChatId, MessageId)>>> = OnceLock::new();
+
+#[derive(Parser)]
+#[command(about = "Telegram bot that replies to \"cat.jpg\" with an image of a cat")]
+struct Cli {
+  #[command(subcommand)]
+  command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+  /// Run one search and print candidate URLs without connecting to Telegram.
+  /// Exits 0 when URLs were found, 1 when the search failed or found nothing.
+  Query {
+    query: String,
+    #[arg(long)]
+    gif: bool,
+  },
+}
+
+fn main() {
+  // Before the logger starts, so RUST_LOG can come from the file
+  let config = load_config_file();
+  let mut logger = pretty_env_logger::formatted_builder();
+  if let Ok(filters) = setting("RUST_LOG") {
+    logger.parse_filters(&filters);
+  }
+  logger.init();
+  match config {
+    Ok(Some((path, applied))) => info!("Loaded {} setting(s) from {}", applied, path),
+    Ok(None) => {}
+    Err(e) => {
+      error!("Invalid config file: {}", e);
+      std::process::exit(1);
+    }
+  }
+
+  run();
+}
+
 #[tokio::main]
-async fn main() {
-  pretty_env_logger::init();
+async fn run() {
+  let cli = Cli::parse();
+  if let Some(CliCommand::Query { query, gif }) = cli.command {
+    std::process::exit(run_query(&query, gif).await);
+  }
+
   info!("Starting image search bot...");
+  let mut bots = Vec::new();
+  for (index, token) in bot_tokens().into_iter().enumerate() {
+    bots.push(connect_bot(index, token).await);
+  }
   initialize_image_search().await;
-  let bot = Bot::from_env();
+  load_aliases();
+
+  let handler = build_handler();
+
+  // One dispatcher (and dialogue storage) per token; search settings are shared
+  let mut shutdown_tokens = Vec::new();
+  let dispatchers: Vec<_> = bots
+    .into_iter()
+    .map(|(bot, bot_info)| {
+      let mut dispatcher = Dispatcher::builder(bot, handler.clone())
+        .dependencies(dptree::deps![dialogue::new_storage(), bot_info])
+        .build();
+      shutdown_tokens.push(dispatcher.shutdown_token());
+      tokio::spawn(async move { dispatcher.dispatch().await })
+    })
+    .collect();
+  tokio::spawn(shutdown_on_ctrlc(shutdown_tokens));
+  #[cfg(unix)]
+  tokio::spawn(reload_on_sighup());
+
+  for dispatcher in dispatchers {
+    if let Err(e) = dispatcher.await {
+      error!("Dispatcher task failed: {:?}", e);
+    }
+  }
+}
+
+// SIGHUP re-reads the config file, like /reloadconfig
+#[cfg(unix)]
+async fn reload_on_sighup() {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  let mut hangups = match signal(SignalKind::hangup()) {
+    Ok(hangups) => hangups,
+    Err(e) => {
+      error!("Failed to listen for SIGHUP: {}", e);
+      return;
+    }
+  };
+  while hangups.recv().await.is_some() {
+    info!("SIGHUP received; {}", describe_reload().replace('\n', "; "));
+  }
+}
+
+// Stop taking updates on Ctrl-C, give in-flight handlers the drain window, then exit
+async fn shutdown_on_ctrlc(shutdown_tokens: Vec<ShutdownToken>) {
+  if let Err(e) = tokio::signal::ctrl_c().await {
+    error!("Failed to listen for Ctrl-C: {}", e);
+    return;
+  }
+
+  let pending = in_flight();
+  let timeout = drain_timeout();
+  info!(
+    "Shutting down; waiting up to {}s for {} in-flight update(s)",
+    timeout.as_secs(),
+    pending
+  );
+  for token in &shutdown_tokens {
+    // Only errors if the dispatcher is already idle or stopping
+    let _ = token.shutdown();
+  }
+
+  let remaining = drain(timeout).await;
+  info!(
+    "Drained {} in-flight update(s); {} cut off",
+    pending.saturating_sub(remaining),
+    remaining
+  );
+  std::process::exit(0);
+}
 
-  let handler = dptree::entry()
-    .branch(Update::filter_message().endpoint(message_handler))
+fn build_handler() -> UpdateHandler<anyhow::Error> {
+  let mut handler = dptree::entry()
+    .filter(is_update_allowed)
+    // Dropped once the update has been fully handled
+    .map(|| Arc::new(InFlight::start()))
+    .branch(
+      Update::filter_message()
+        .enter_dialogue::<Message, ChatStorage, ChatState>()
+        .branch(dptree::filter(dialogue::is_cancel_command).endpoint(dialogue::handle_cancel))
+        .endpoint(message_handler),
+    )
+    .branch(Update::filter_edited_message().endpoint(edited_message_handler))
     .branch(Update::filter_inline_query().endpoint(handle_inline_query))
     .branch(Update::filter_chosen_inline_result().endpoint(handle_chosen_inline_result));
 
-  Dispatcher::builder(bot, handler)
-    .enable_ctrlc_handler()
-    .build()
-    .dispatch()
-    .await;
+  if env_flag("HANDLE_CHANNEL_POSTS") {
+    info!("HANDLE_CHANNEL_POSTS enabled; channel posts will trigger searches.");
+    handler = handler.branch(Update::filter_channel_post().endpoint(message_handler));
+  }
+
+  if let Some(emoji) = trigger_emoji() {
+    info!(
+      "REACTION_TRIGGER_EMOJI set; reacting with {} triggers a search.",
+      emoji
+    );
+    handler = handler.branch(Update::filter_message_reaction_updated().endpoint(reaction_handler));
+  }
+
+  handler
+}
+
+// Query mode: print "<engine>\t<url>" per candidate and return the process exit code
+// Exits 0 when anything usable was printed, including a featured pin whose search then failed.
+// Aliases are per chat and none exist outside one, so only stopwords and the banned list apply.
+async fn run_query(query: &str, is_gif: bool) -> i32 {
+  let query = strip_stopwords(query, stopwords());
+  if let Some(word) = find_banned_word(&query, banned_words()) {
+    eprintln!("Refused: '{}' is in BANNED_QUERY_WORDS", word);
+    return 1;
+  }
+
+  let featured_url = find_featured(&query, featured());
+  if let Some(image_url) = featured_url {
+    println!("featured\t{}", image_url);
+  }
+
+  match image_search(&query, is_gif, &SearchLocale::resolve(None)).await {
+    Ok(results) => {
+      for result in results {
+        println!("{}\t{}", result.engine.label(), result.url);
+      }
+      0
+    }
+    Err(e) => {
+      eprintln!("Search failed: {}", e);
+      if featured_url.is_some() {
+        0
+      } else {
+        1
+      }
+    }
+  }
+}
+
+// TELOXIDE_TOKENS (comma-separated) runs several bots from one process; otherwise TELOXIDE_TOKEN
+fn bot_tokens() -> Vec<String> {
+  let tokens: Vec<String> = setting("TELOXIDE_TOKENS")
+    .or_else(|_| setting("TELOXIDE_TOKEN"))
+    .unwrap_or_default()
+    .split(',')
+    .map(str::trim)
+    .filter(|token| !token.is_empty())
+    .map(String::from)
+    .collect();
+
+  if tokens.is_empty() {
+    error!("TELOXIDE_TOKEN is not set. Get a token from @BotFather and export it before starting.");
+    std::process::exit(1);
+  }
+  tokens
+}
+
+// Fail fast with a clear message instead of a late API error
+async fn connect_bot(index: usize, token: String) -> (Bot, BotInfo) {
+  let bot = Bot::with_client(token, teloxide::net::client_from_env());
+  let username = match bot.get_me().await {
+    Ok(me) => me.username().to_string(),
+    Err(e) => {
+      error!("Bot token #{} was rejected by Telegram: {}", index + 1, e);
+      std::process::exit(1);
+    }
+  };
+  info!("Logged in as @{}", username);
+
+  (bot, BotInfo { username })
 }
 
-async fn message_handler(bot: Bot, msg: Message) -> Result<(), anyhow::Error> {
-  let text = match msg.text() {
-    Some(text) => text,
-    None => return Ok(()),
+async fn message_handler(bot: Bot, msg: Message, bot_info: BotInfo) -> Result<(), anyhow::Error> {
+  let Some(text) = trigger_text(&msg) else {
+    return Ok(());
   };
 
-  // Handle commands
-  if text.starts_with('/') {
-    return handle_command(&bot, &msg).await;
+  if held_for_maintenance(
+    &bot,
+    &msg,
+    text,
+    maintenance_enabled(),
+    env_flag("MAINTENANCE_NOTIFY"),
+  )
+  .await?
+  {
+    return Ok(());
+  }
+
+  // Handle commands; "/foo.jpg" isn't one, so SEARCH_UNKNOWN_COMMANDS lets it fall through
+  if msg.text().is_some()
+    && text.starts_with('/')
+    && (handle_command(&bot, &msg, &bot_info).await? || !env_flag("SEARCH_UNKNOWN_COMMANDS"))
+  {
+    return Ok(());
   }
 
-  // Exclude URLs and only match patterns like "cat.jpg" or "dog.gif"
-  if text.contains("://") {
+  if let Some(emoji) = trigger_emoji() {
+    if is_trigger_reply(&msg, emoji) {
+      if !user_allowed(msg.from.as_ref()) {
+        return Ok(());
+      }
+      return search_replied_text(&bot, &msg).await;
+    }
+    remember_text(msg.chat.id, msg.id, text);
+  }
+
+  let (trigger_text, count) = split_count_suffix(text);
+  let (query, is_gif) = match parse_trigger(trigger_text)? {
+    Some(trigger) => trigger,
+    None => match hashtag_query(&msg) {
+      Some(query) => (query, false),
+      None => return Ok(()),
+    },
+  };
+  if !user_allowed(msg.from.as_ref()) {
+    return Ok(());
+  }
+  mark_responded(msg.chat.id, msg.id);
+
+  let locale = user_locale(msg.from.as_ref());
+  let reply_to = reply_target(msg.chat.id, msg.id);
+  search_with_timeout(&bot, msg.chat.id, reply_to, &query, is_gif, count, &locale).await
+}
+
+// The text that may trigger a search; captioned photos/documents count too. Messages
+// from bots are ignored so captioned images they post (including ours) never loop back.
+// Whether maintenance swallows the message; commands are answered with a notice when `notify`
+// is set. Admins keep their commands so maintenance can be switched off again.
+async fn held_for_maintenance(
+  bot: &Bot,
+  msg: &Message,
+  text: &str,
+  maintenance: bool,
+  notify: bool,
+) -> Result<bool, anyhow::Error> {
+  if !maintenance || is_admin(msg.from.as_ref()) {
+    return Ok(false);
+  }
+
+  if notify && msg.text().is_some() && text.starts_with('/') {
+    send_gate::acquire().await;
+    bot
+      .send_message(
+        msg.chat.id,
+        "The bot is under maintenance, please try again later.",
+      )
+      .await?;
+  }
+  Ok(true)
+}
+
+fn trigger_text(msg: &Message) -> Option<&str> {
+  if msg.from.as_ref().is_some_and(|user| user.is_bot) {
+    return None;
+  }
+  msg.text().or(msg.caption())
+}
+
+// Search using the text of a message someone reacted to with the trigger emoji
+async fn reaction_handler(bot: Bot, reaction: MessageReactionUpdated) -> Result<(), anyhow::Error> {
+  let Some(emoji) = trigger_emoji() else {
+    return Ok(());
+  };
+  if maintenance_enabled()
+    || !is_trigger_reaction(&reaction, emoji)
+    || !user_allowed(reaction.user.as_ref())
+  {
+    return Ok(());
+  }
+
+  match recall_text(reaction.chat.id, reaction.message_id) {
+    Some(text) => {
+      let locale = user_locale(reaction.user.as_ref());
+      let reply_to = reply_target(reaction.chat.id, reaction.message_id);
+      search_text(&bot, reaction.chat.id, reply_to, &text, &locale).await
+    }
+    None => {
+      info!(
+        "No known text for message {} in chat {}; ignoring reaction",
+        reaction.message_id, reaction.chat.id
+      );
+      Ok(())
+    }
+  }
+}
+
+// Per-user throttle on top of the global send gate; anonymous senders aren't throttled
+fn user_allowed(user: Option<&User>) -> bool {
+  match user {
+    Some(user) => allow_search(user.id),
+    None => true,
+  }
+}
+
+// Search using the text of the message the trigger emoji replied to
+async fn search_replied_text(bot: &Bot, msg: &Message) -> Result<(), anyhow::Error> {
+  let target = msg.reply_to_message();
+  match target.and_then(|target| target.text().or(target.caption())) {
+    Some(text) => {
+      let reply_to = reply_target(msg.chat.id, msg.id);
+      search_text(
+        bot,
+        msg.chat.id,
+        reply_to,
+        text,
+        &user_locale(msg.from.as_ref()),
+      )
+      .await
+    }
+    None => {
+      send_gate::acquire().await;
+      bot
+        .send_message(msg.chat.id, "That message has no text to search.")
+        .await?;
+      Ok(())
+    }
+  }
+}
+
+// Search arbitrary message text, honoring a ".gif" suffix if present
+async fn search_text(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  text: &str,
+  locale: &SearchLocale,
+) -> Result<(), anyhow::Error> {
+  if is_too_long(text) {
+    return Ok(());
+  }
+
+  let (text, count) = split_count_suffix(text);
+  let (query, is_gif) = parse_trigger(text)?.unwrap_or_else(|| (text.trim().to_string(), false));
+  search_with_timeout(bot, chat_id, reply_to, &query, is_gif, count, locale).await
+}
+
+async fn search_with_timeout(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  query: &str,
+  is_gif: bool,
+  count: Option<usize>,
+  locale: &SearchLocale,
+) -> Result<(), anyhow::Error> {
+  let resolved = resolve_alias(chat_id, &strip_stopwords(query, stopwords()));
+  if refuse_banned(bot, chat_id, &[query, &resolved]).await? {
     return Ok(());
   }
+  let query = resolved;
+
+  // Bound the whole search-then-send flow so one slow query can't hang forever
+  match with_handler_timeout(
+    chat_id,
+    handler_timeout(),
+    &format!("handling '{}'", query),
+    try_online(bot, chat_id, reply_to, &query, is_gif, count, locale),
+  )
+  .await
+  {
+    Some(result) => result,
+    None => {
+      if env_flag("HANDLER_TIMEOUT_NOTIFY") {
+        send_gate::acquire().await;
+        bot
+          .send_message(chat_id, "Sorry, the image search took too long.")
+          .await?;
+      }
+      Ok(())
+    }
+  }
+}
+
+// Run a handler future for at most `timeout`, recording a timeout as the chat's last
+// error. Returns None if the future was cut off.
+async fn with_handler_timeout<T>(
+  chat_id: ChatId,
+  timeout: Duration,
+  action: &str,
+  future: impl std::future::Future<Output = T>,
+) -> Option<T> {
+  match tokio::time::timeout(timeout, future).await {
+    Ok(output) => Some(output),
+    Err(_) => {
+      error!("Timed out after {}s {}", timeout.as_secs(), action);
+      record_error(
+        chat_id,
+        format!("timed out after {}s {}", timeout.as_secs(), action),
+      );
+      None
+    }
+  }
+}
+
+// Refuse a query containing a BANNED_QUERY_WORDS term before anything is searched.
+// Callers pass the query as typed and as resolved, since an alias can introduce a term.
+async fn refuse_banned(
+  bot: &Bot,
+  chat_id: ChatId,
+  queries: &[&str],
+) -> Result<bool, anyhow::Error> {
+  let Some(word) = queries
+    .iter()
+    .find_map(|query| find_banned_word(query, banned_words()))
+  else {
+    return Ok(false);
+  };
+
+  info!("Refusing query in chat {}: banned word '{}'", chat_id, word);
+  if env_flag("BANNED_QUERY_NOTIFY") {
+    send_gate::acquire().await;
+    bot
+      .send_message(chat_id, "Sorry, that search isn't allowed.")
+      .await?;
+  }
+  Ok(true)
+}
+
+// Split a trailing count like "cat.jpg x3" off the trigger, capped at one media group
+fn split_count_suffix(text: &str) -> (&str, Option<usize>) {
+  let Some((trigger, count)) = text.trim_end().rsplit_once(char::is_whitespace) else {
+    return (text, None);
+  };
+  let count = count
+    .strip_prefix(['x', 'X', '×'])
+    .and_then(|count| count.parse::<usize>().ok())
+    .filter(|count| *count > 0);
+  match count {
+    Some(count) => (trigger.trim_end(), Some(count.min(MAX_ALBUM_SIZE))),
+    None => (text, None),
+  }
+}
+
+// Split "cat.jpg" / "cat.jpeg" / "dog.gif" into the search query and whether a GIF was requested
+fn parse_trigger(text: &str) -> Result<Option<(String, bool)>, anyhow::Error> {
+  // Exclude URLs and only match patterns like "cat.jpg" or "dog.gif"
+  if is_too_long(text) || text.contains("://") {
+    return Ok(None);
+  }
 
-  let pattern = Regex::new(r"(?i)^(.+)\.(jpg|png|gif)$")?;
+  let pattern = Regex::new(r"(?i)^(.+)\.(jpe?g|png|gif)$")?;
   let captures = match pattern.captures(text) {
     Some(c) => c,
-    None => return Ok(()),
+    None => return Ok(None),
   };
 
-  let query = captures.get(1).unwrap().as_str();
+  let query = captures.get(1).unwrap().as_str().to_string();
   let is_gif = captures.get(2).unwrap().as_str().to_lowercase() == "gif";
+  Ok(Some((query, is_gif)))
+}
 
-  let image_urls = image_search(query, is_gif).await?;
+// First hashtag in the message as a query ("#mic_drop" -> "mic drop"), when enabled for the chat
+fn hashtag_query(msg: &Message) -> Option<String> {
+  if !hashtags_enabled(msg.chat.id) {
+    return None;
+  }
 
-  for image_url in image_urls.iter() {
-    let result = if is_imgur_url(image_url) {
-      match download_imgur_image(image_url).await {
-        Ok(data) => {
-          let input_file = InputFile::memory(data);
-          if is_gif {
-            bot.send_animation(msg.chat.id, input_file).await
-          } else {
-            bot.send_photo(msg.chat.id, input_file).await
-          }
-        }
-        Err(e) => {
-          error!("Failed to download imgur image {}: {:?}", image_url, e);
-          continue;
-        }
+  let entities = msg
+    .parse_entities()
+    .or_else(|| msg.parse_caption_entities())?;
+  entities
+    .iter()
+    .filter(|entity| matches!(entity.kind(), MessageEntityKind::Hashtag))
+    .map(|entity| entity.text().trim_start_matches('#').replace('_', " "))
+    .find(|query| !query.trim().is_empty())
+}
+
+// Search locale for a requester, honoring SEARCH_LOCALE and their Telegram language
+fn user_locale(user: Option<&User>) -> SearchLocale {
+  SearchLocale::resolve(user.and_then(|user| user.language_code.as_deref()))
+}
+
+// Long messages are chat, not image names; skip them before regex matching and searching
+fn is_too_long(text: &str) -> bool {
+  text.chars().nth(MAX_QUERY_CHARS).is_some()
+}
+
+// Run the trigger logic again when a message is edited into "cat.jpg",
+// unless the edit is stale or the original message was already answered
+async fn edited_message_handler(
+  bot: Bot,
+  msg: Message,
+  bot_info: BotInfo,
+) -> Result<(), anyhow::Error> {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs() as i64)
+    .unwrap_or_default();
+  if !is_fresh_unanswered_edit(&msg, now) {
+    return Ok(());
+  }
+
+  message_handler(bot, msg, bot_info).await
+}
+
+fn is_fresh_unanswered_edit(msg: &Message, now: i64) -> bool {
+  let Some(edit_date) = msg.edit_date() else {
+    return false;
+  };
+
+  now - edit_date.timestamp() <= EDIT_MAX_AGE_SECS
+    && !msg.text().is_some_and(|text| text.starts_with('/'))
+    && !has_responded(msg.chat.id, msg.id)
+}
+
+fn responded_messages() -> std::sync::MutexGuard<'static, VecDeque<(ChatId, MessageId)>> {
+  RESPONDED_MESSAGES
+    .get_or_init(|| Mutex::new(VecDeque::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn mark_responded(chat_id: ChatId, message_id: MessageId) {
+  let mut responded = responded_messages();
+  if responded.len() >= RESPONDED_CAPACITY {
+    responded.pop_front();
+  }
+  responded.push_back((chat_id, message_id));
+}
+
+fn has_responded(chat_id: ChatId, message_id: MessageId) -> bool {
+  responded_messages().contains(&(chat_id, message_id))
+}
+
+// Search online and send the first candidate Telegram accepts
+async fn try_online(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  query: &str,
+  is_gif: bool,
+  count: Option<usize>,
+  locale: &SearchLocale,
+) -> Result<(), anyhow::Error> {
+  let started = Instant::now();
+  let caption = query_caption_enabled(chat_id).then_some(query);
+
+  // Curated pins skip scoring entirely; fall through to search only if the pin fails to send
+  if let Some(image_url) = find_featured(query, featured()) {
+    match send_image(bot, chat_id, reply_to, image_url, is_gif, caption).await {
+      Ok(()) => {
+        info!("Sent featured {} {}", media_kind(is_gif), image_url);
+        info!(
+          "[timing] featured query '{}' took {} ms",
+          query,
+          started.elapsed().as_millis()
+        );
+        record_duration(Branch::Featured, started.elapsed());
+        return Ok(());
       }
-    } else {
-      let parsed_url = match Url::parse(image_url) {
-        Ok(url) => url,
-        Err(_) => {
-          error!("Failed to parse URL: {}", image_url);
-          continue;
-        }
-      };
+      Err(e) if is_missing_rights(&e) => {
+        report_missing_rights(bot, chat_id, is_gif, &e).await;
+        return Ok(());
+      }
+      Err(e) if is_chat_unreachable(&e) => {
+        warn!("Chat {} is unreachable, not sending: {:#}", chat_id, e);
+        return Ok(());
+      }
+      Err(e) => {
+        error!("Failed to send featured image {}: {:?}", image_url, e);
+        record_error(
+          chat_id,
+          format!("failed to send featured {}: {:#}", image_url, e),
+        );
+      }
+    }
+  }
 
-      if is_gif {
-        bot
-          .send_animation(msg.chat.id, InputFile::url(parsed_url))
-          .await
-      } else {
-        bot
-          .send_photo(msg.chat.id, InputFile::url(parsed_url))
-          .await
+  // Repeated failures usually mean an outage, so stop scraping until the cooldown passes
+  if !allow_online() {
+    info!("Online search is paused; skipping query '{}'", query);
+    record_error(
+      chat_id,
+      format!(
+        "online search paused after repeated failures; skipped '{}'",
+        query
+      ),
+    );
+    send_not_found(bot, chat_id, reply_to).await;
+    return Ok(());
+  }
+
+  let search_started = Instant::now();
+  let mut results = match image_search(query, is_gif, locale).await {
+    Ok(results) => results,
+    Err(e) => {
+      record_error(chat_id, format!("search for '{}' failed: {:#}", query, e));
+      send_not_found(bot, chat_id, reply_to).await;
+      return Err(e);
+    }
+  };
+  result_order(chat_id).apply(&mut results);
+  let search_elapsed = search_started.elapsed();
+  let send_started = Instant::now();
+  // GIFs can't share a media group, so several are sent one animation at a time
+  let wanted = match count {
+    Some(count) if is_gif => count.min(MAX_GIF_COUNT),
+    Some(count) => count,
+    None if is_gif => gif_count(),
+    None => 1,
+  };
+  let mut sent = 0;
+
+  // "cat.jpg x3" asks for an album; if Telegram rejects any URL in it, fall back to single sends
+  if !is_gif && wanted > 1 && results.len() > 1 {
+    let album: Vec<&str> = results
+      .iter()
+      .take(wanted)
+      .map(|result| result.url.as_str())
+      .collect();
+    match send_album(bot, chat_id, reply_to, &album, caption).await {
+      Ok(()) => {
+        info!("Sent album of {} photos for '{}'", album.len(), query);
+        sent = album.len();
       }
-    };
+      Err(e) if is_chat_unreachable(&e) => {
+        warn!("Chat {} is unreachable, not sending: {:#}", chat_id, e);
+        return Ok(());
+      }
+      Err(e) => {
+        warn!(
+          "Failed to send album for '{}', sending photos one at a time: {:?}",
+          query, e
+        );
+        record_error(chat_id, format!("failed to send album: {:#}", e));
+      }
+    }
+  }
+
+  // Candidates already in a sent album aren't tried again
+  let Some(sent_singly) = send_candidates(
+    bot,
+    chat_id,
+    reply_to,
+    &results[sent..],
+    wanted.saturating_sub(sent),
+    is_gif,
+    caption,
+  )
+  .await
+  else {
+    return Ok(());
+  };
+  let sent = sent + sent_singly;
+
+  // Only sends count towards the breaker; a query with no results says nothing about Telegram
+  if sent > 0 {
+    record_online_success();
+  } else if !results.is_empty() {
+    record_online_failure();
+  }
+
+  if sent == 0 {
+    send_last_resort(
+      bot,
+      chat_id,
+      reply_to,
+      results.first(),
+      env_flag("SEND_LINK_ON_FAILURE"),
+    )
+    .await;
+  }
+
+  info!(
+    "[timing] online query '{}' took {} ms (search {} ms, send {} ms)",
+    query,
+    started.elapsed().as_millis(),
+    search_elapsed.as_millis(),
+    send_started.elapsed().as_millis()
+  );
+  record_duration(Branch::Online, started.elapsed());
+
+  Ok(())
+}
 
-    match result {
-      Ok(_) => break,
+// Send candidates in order until `wanted` have gone out; failed sends move on to the next one.
+// Returns how many were sent, or None when the chat can't receive anything, so the caller
+// should stop.
+async fn send_candidates(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  candidates: &[SearchResult],
+  wanted: usize,
+  is_gif: bool,
+  caption: Option<&str>,
+) -> Option<usize> {
+  let send_delay = send_delay();
+  let mut sent = 0;
+  for (attempt, search_result) in candidates.iter().enumerate() {
+    if sent >= wanted {
+      break;
+    }
+    // Pace consecutive send attempts so fallbacks don't trip Telegram's flood limits
+    if attempt > 0 && !send_delay.is_zero() {
+      tokio::time::sleep(send_delay).await;
+    }
+
+    let image_url = &search_result.url;
+    match send_image(bot, chat_id, reply_to, image_url, is_gif, caption).await {
+      Ok(()) => {
+        info!(
+          "Sent {} {} (from {})",
+          media_kind(is_gif),
+          image_url,
+          search_result.engine.label()
+        );
+        sent += 1;
+      }
+      // Every candidate would fail the same way, so stop instead of spamming errors
+      Err(e) if is_missing_rights(&e) => {
+        report_missing_rights(bot, chat_id, is_gif, &e).await;
+        return None;
+      }
+      // The user blocked the bot or it left the group; nothing more can be delivered
+      Err(e) if is_chat_unreachable(&e) => {
+        warn!(
+          "Chat {} is unreachable, skipping remaining candidates: {:#}",
+          chat_id, e
+        );
+        return None;
+      }
       Err(e) => {
         error!(
           "Failed to send {} {}: {:?}",
-          if is_gif { "animation" } else { "photo" },
+          media_kind(is_gif),
           image_url,
           e
         );
+        record_error(chat_id, format!("failed to send {}: {:#}", image_url, e));
         continue;
       }
     }
   }
 
+  Some(sent)
+}
+
+// Warn once per chat when the bot may not post media there, and explain in text if allowed
+async fn report_missing_rights(bot: &Bot, chat_id: ChatId, is_gif: bool, e: &anyhow::Error) {
+  record_error(
+    chat_id,
+    format!("not allowed to send {}: {:#}", media_kind(is_gif), e),
+  );
+  if !first_notice(chat_id) {
+    return;
+  }
+
+  warn!(
+    "Bot lacks rights to send {} in chat {}: {:#}",
+    media_kind(is_gif),
+    chat_id,
+    e
+  );
+  send_gate::acquire().await;
+  let notice = format!(
+    "I don't have permission to send {}s in this chat. Ask an admin to allow media for me.",
+    media_kind(is_gif)
+  );
+  if let Err(e) = bot.send_message(chat_id, notice).await {
+    warn!(
+      "Could not explain missing rights in chat {}: {:?}",
+      chat_id, e
+    );
+  }
+}
+
+// Nothing could be sent: let the user open the best candidate in a browser when
+// SEND_LINK_ON_FAILURE is set, else fall back to NOT_FOUND_IMAGE
+async fn send_last_resort(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  best: Option<&SearchResult>,
+  link_on_failure: bool,
+) {
+  match best {
+    Some(best) if link_on_failure => {
+      send_link(bot, chat_id, reply_to, &best.url).await;
+    }
+    _ => send_not_found(bot, chat_id, reply_to).await,
+  }
+}
+
+async fn send_link(bot: &Bot, chat_id: ChatId, reply_to: Option<MessageId>, image_url: &str) {
+  send_gate::acquire().await;
+  let mut request = bot.send_message(
+    chat_id,
+    format!("Couldn't send the image, but here it is: {}", image_url),
+  );
+  request.payload_mut().reply_parameters = reply_to.map(reply_parameters);
+  if let Err(e) = request.await {
+    error!("Failed to send link {}: {:?}", image_url, e);
+    record_error(
+      chat_id,
+      format!("failed to send link {}: {:#}", image_url, e),
+    );
+  }
+}
+
+// Send several photo URLs as one media group; a caption goes on the first photo
+async fn send_album(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  image_urls: &[&str],
+  caption: Option<&str>,
+) -> Result<(), anyhow::Error> {
+  let media = image_urls
+    .iter()
+    .enumerate()
+    .map(|(index, image_url)| {
+      let url = Url::parse(image_url).context("failed to parse URL")?;
+      let mut photo = InputMediaPhoto::new(InputFile::url(url));
+      photo.caption = caption.filter(|_| index == 0).map(str::to_string);
+      Ok(InputMedia::Photo(photo))
+    })
+    .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+  send_gate::acquire().await;
+  let mut request = bot.send_media_group(chat_id, media);
+  request.payload_mut().reply_parameters = reply_to.map(reply_parameters);
+  request.await?;
   Ok(())
 }
 
-async fn handle_command(bot: &Bot, msg: &Message) -> Result<(), anyhow::Error> {
-  let text = msg.text().unwrap();
+// Send NOT_FOUND_IMAGE (a URL or local file path) when nothing could be sent
+async fn send_not_found(bot: &Bot, chat_id: ChatId, reply_to: Option<MessageId>) {
+  let Ok(image) = setting("NOT_FOUND_IMAGE") else {
+    return;
+  };
+  let image = image.trim();
+  if image.is_empty() {
+    return;
+  }
 
-  match text {
-    "/start" => {
-      bot
-        .send_message(
-          msg.chat.id,
-          "Welcome! Send me a message like \"cat.jpg\" or \"dog.gif\" to search for images.\n\
-           You can also use me in any chat by typing @botname followed by your search term!\n\
-           See https://github.com/akira02/rust-tg.jpg for more information.",
-        )
-        .await?;
+  let input_file = match Url::parse(image) {
+    Ok(url) if matches!(url.scheme(), "http" | "https") => InputFile::url(url),
+    _ => InputFile::file(image),
+  };
+  send_gate::acquire().await;
+  let mut request = bot.send_photo(chat_id, input_file);
+  request.payload_mut().reply_parameters = reply_to.map(reply_parameters);
+  if let Err(e) = request.await {
+    error!("Failed to send NOT_FOUND_IMAGE {}: {:?}", image, e);
+    record_error(
+      chat_id,
+      format!("failed to send NOT_FOUND_IMAGE {}: {:#}", image, e),
+    );
+  }
+}
+
+// Send one image URL with the method for its extension (photo or animation by default),
+// uploading it ourselves for download domains
+async fn send_image(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  image_url: &str,
+  is_gif: bool,
+  caption: Option<&str>,
+) -> Result<(), anyhow::Error> {
+  let (method, download) = send_route(
+    image_url,
+    is_gif,
+    send_method_overrides(),
+    download_domains(),
+  );
+  let bot_id = bot_id(bot);
+
+  // A file Telegram already holds skips both our download and its fetch of the URL
+  if let Some((sent, file_id)) = cached_file_id(bot_id, image_url, method) {
+    match send_input_file(
+      bot,
+      chat_id,
+      reply_to,
+      image_url,
+      sent,
+      InputFile::file_id(file_id),
+      caption,
+    )
+    .await
+    {
+      Ok(_) => return Ok(()),
+      Err(e) if is_stale_file_id(&e) => {
+        warn!(
+          "Cached file ID for {} was rejected; sending the URL",
+          image_url
+        );
+        forget_file_id(bot_id, image_url);
+      }
+      Err(e) => return Err(e),
     }
-    _ => {}
   }
 
+  let input_file = if download {
+    let data = download_image(image_url)
+      .await
+      .context("failed to download image")?;
+    let data = if method == SendMethod::Photo {
+      prepare_photo(image_url, data).await?
+    } else {
+      ensure_complete_gif(image_url, data).await?
+    };
+    InputFile::memory(data)
+  } else {
+    InputFile::url(Url::parse(image_url).context("failed to parse URL")?)
+  };
+
+  let (message, sent) = send_input_file(
+    bot, chat_id, reply_to, image_url, method, input_file, caption,
+  )
+  .await?;
+  if let Some(file_id) = sent_file_id(&message, sent) {
+    remember_file_id(bot_id, image_url, method, sent, file_id);
+  }
   Ok(())
 }
+
+// The send method for a URL, and whether the bot downloads it rather than passing the URL
+fn send_route(
+  image_url: &str,
+  is_gif: bool,
+  overrides: &HashMap<String, SendMethod>,
+  domains: &[String],
+) -> (SendMethod, bool) {
+  (
+    send_method_for(image_url, is_gif, overrides),
+    should_download(image_url, domains),
+  )
+}
+
+// Send with the given method, returning the message and the method that actually worked
+async fn send_input_file(
+  bot: &Bot,
+  chat_id: ChatId,
+  reply_to: Option<MessageId>,
+  image_url: &str,
+  method: SendMethod,
+  input_file: InputFile,
+  caption: Option<&str>,
+) -> Result<(Message, SendMethod), anyhow::Error> {
+  let reply_parameters = reply_to.map(reply_parameters);
+  let caption = caption.map(str::to_string);
+  send_gate::acquire().await;
+  let message = match method {
+    SendMethod::Photo => {
+      let mut request = bot.send_photo(chat_id, input_file);
+      request.payload_mut().reply_parameters = reply_parameters;
+      request.payload_mut().caption = caption;
+      request.await?
+    }
+    SendMethod::Animation => {
+      let mut request = bot.send_animation(chat_id, input_file.clone());
+      request.payload_mut().reply_parameters = reply_parameters.clone();
+      request.payload_mut().caption = caption.clone();
+      match request.await {
+        Ok(message) => message,
+        // Telegram rejects some animated WebP as animations but accepts them as files
+        Err(e) if is_webp(image_url) => {
+          warn!(
+            "Animation rejected for {}, sending as document: {}",
+            image_url, e
+          );
+          send_gate::acquire().await;
+          let mut request = bot.send_document(chat_id, input_file);
+          request.payload_mut().reply_parameters = reply_parameters;
+          request.payload_mut().caption = caption;
+          return Ok((request.await?, SendMethod::Document));
+        }
+        Err(e) => return Err(e.into()),
+      }
+    }
+    SendMethod::Document => {
+      let mut request = bot.send_document(chat_id, input_file);
+      request.payload_mut().reply_parameters = reply_parameters;
+      request.payload_mut().caption = caption;
+      request.await?
+    }
+    // Stickers can't carry a caption
+    SendMethod::Sticker => {
+      let mut request = bot.send_sticker(chat_id, input_file);
+      request.payload_mut().reply_parameters = reply_parameters;
+      request.await?
+    }
+  };
+
+  Ok((message, method))
+}
+
+// File IDs belong to the bot that received them; the token's numeric prefix is the bot's ID
+fn bot_id(bot: &Bot) -> &str {
+  bot.token().split(':').next().unwrap_or_default()
+}
+
+fn is_webp(image_url: &str) -> bool {
+  Url::parse(image_url).is_ok_and(|url| url.path().to_lowercase().ends_with(".webp"))
+}
+
+// Still send the image if the triggering message was deleted in the meantime
+fn reply_parameters(message_id: MessageId) -> ReplyParameters {
+  ReplyParameters::new(message_id).allow_sending_without_reply()
+}
+
+fn media_kind(is_gif: bool) -> &'static str {
+  if is_gif {
+    "animation"
+  } else {
+    "photo"
+  }
+}
+
+// Read HANDLER_TIMEOUT_SECS, falling back to the default on missing or invalid values
+fn handler_timeout() -> Duration {
+  let secs = setting("HANDLER_TIMEOUT_SECS")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .filter(|secs| *secs > 0)
+    .unwrap_or(DEFAULT_HANDLER_TIMEOUT_SECS);
+  Duration::from_secs(secs)
+}
+
+// Animations sent per GIF query, from GIF_COUNT (default 1, at most MAX_GIF_COUNT)
+fn gif_count() -> usize {
+  setting("GIF_COUNT")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .filter(|count| *count > 0)
+    .unwrap_or(1)
+    .min(MAX_GIF_COUNT)
+}
+
+// Pause between consecutive sends, from SEND_DELAY_MS (default 0, no delay)
+fn send_delay() -> Duration {
+  let millis = setting("SEND_DELAY_MS")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(0);
+  Duration::from_millis(millis)
+}
+
+fn env_flag(key: &str) -> bool {
+  setting(key)
+    .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false)
+}
+
+// Returns false when the text isn't one of our commands
+async fn handle_command(
+  bot: &Bot,
+  msg: &Message,
+  bot_info: &BotInfo,
+) -> Result<bool, anyhow::Error> {
+  let text = msg.text().unwrap();
+  let (command, args) = match text.split_once(char::is_whitespace) {
+    Some((command, args)) => (command, args.trim()),
+    None => (text, ""),
+  };
+  // "/start@other_bot" in a group is meant for someone else
+  let Some(command) = bot_info.strip_command_suffix(command) else {
+    return Ok(true);
+  };
+
+  let message_locale = MessageLocale::from_language_code(
+    msg
+      .from
+      .as_ref()
+      .and_then(|user| user.language_code.as_deref()),
+  );
+  match command {
+    "/start" => {
+      send_gate::acquire().await;
+      bot
+        .send_message(
+          msg.chat.id,
+          bot_info.fill_username(&welcome_message(message_locale)),
+        )
+        .await?;
+    }
+    "/help" => {
+      send_gate::acquire().await;
+      bot
+        .send_message(
+          msg.chat.id,
+          bot_info.fill_username(&help_message(message_locale)),
+        )
+        .await?;
+    }
+    "/feedback" => forward_feedback(bot, msg, args).await?,
+    "/flushcache" => {
+      // Every query after a flush hits the engines again, so only admins may flush
+      if is_admin(msg.from.as_ref()) {
+        let searches = clear_search_cache();
+        let downloads = clear_download_cache();
+        info!(
+          "Cleared {} cached search(es) and {} cached download(s) on request",
+          searches, downloads
+        );
+        send_gate::acquire().await;
+        bot
+          .send_message(
+            msg.chat.id,
+            format!(
+              "Cleared {} cached search(es) and {} cached download(s).",
+              searches, downloads
+            ),
+          )
+          .await?;
+      }
+    }
+    "/debug_last" => {
+      // Error details name URLs and engines, which are for the operator
+      if is_admin(msg.from.as_ref()) {
+        let reply = match last_error(msg.chat.id) {
+          Some((ago, detail)) => format!("{}s ago: {}", ago.as_secs(), detail),
+          None => "No errors recorded for this chat.".to_string(),
+        };
+        send_gate::acquire().await;
+        bot
+          .send_message(msg.chat.id, reply)
+          .link_preview_options(no_link_preview())
+          .await?;
+      }
+    }
+    "/reloadconfig" => {
+      if is_admin(msg.from.as_ref()) {
+        send_gate::acquire().await;
+        bot.send_message(msg.chat.id, describe_reload()).await?;
+      }
+    }
+    "/maintenance" => {
+      if is_admin(msg.from.as_ref()) {
+        let reply = match args {
+          "on" | "off" => {
+            set_maintenance(args == "on");
+            info!("Maintenance mode turned {}", args);
+            format!("Maintenance mode is now {}.", args)
+          }
+          _ => format!(
+            "Usage: /maintenance on|off (currently {})",
+            if maintenance_enabled() { "on" } else { "off" }
+          ),
+        };
+        send_gate::acquire().await;
+        bot.send_message(msg.chat.id, reply).await?;
+      }
+    }
+    "/queue" => {
+      // Load details are for the operator, so only answer admins
+      if is_admin(msg.from.as_ref()) {
+        send_gate::acquire().await;
+        bot.send_message(msg.chat.id, describe_queue()).await?;
+      }
+    }
+    "/stats" => {
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, describe_stats()).await?;
+    }
+    "/problems" => {
+      // Failing URLs come from every chat, so only admins see them
+      if is_admin(msg.from.as_ref()) {
+        send_gate::acquire().await;
+        bot
+          .send_message(msg.chat.id, describe_decode_failures())
+          .link_preview_options(no_link_preview())
+          .await?;
+      }
+    }
+    "/reply_on" | "/reply_off" => {
+      let enabled = command == "/reply_on";
+      set_reply_enabled(msg.chat.id, enabled);
+      let reply = if enabled {
+        "Images will now be sent as replies to the triggering message."
+      } else {
+        "Images will no longer be sent as replies."
+      };
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, reply).await?;
+    }
+    "/hashtag_on" | "/hashtag_off" => {
+      let enabled = command == "/hashtag_on";
+      set_hashtags_enabled(msg.chat.id, enabled);
+      let reply = if enabled {
+        "Hashtags like #mic_drop will now trigger a search."
+      } else {
+        "Hashtags will no longer trigger a search."
+      };
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, reply).await?;
+    }
+    "/querycaption_on" | "/querycaption_off" => {
+      let enabled = command == "/querycaption_on";
+      set_query_caption_enabled(msg.chat.id, enabled);
+      let reply = if enabled {
+        "Images found online will now be captioned with the search query."
+      } else {
+        "Images found online will no longer be captioned."
+      };
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, reply).await?;
+    }
+    "/order" => {
+      let reply = match ResultOrder::parse(args) {
+        Some(order) => {
+          set_result_order(msg.chat.id, order);
+          format!("Results will now be sent in {} order.", order.label())
+        }
+        None => format!(
+          "Usage: /order relevance|shuffle (currently {})",
+          result_order(msg.chat.id).label()
+        ),
+      };
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, reply).await?;
+    }
+    "/alias" => {
+      let reply = match args.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+          if set_alias(msg.chat.id, from, to) {
+            save_aliases();
+            format!("\"{}\" will now search for \"{}\".", from.trim(), to.trim())
+          } else {
+            format!(
+              "This chat already has {} aliases; remove one with /unalias first.",
+              MAX_ALIASES_PER_CHAT
+            )
+          }
+        }
+        _ => describe_aliases(msg.chat.id),
+      };
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, reply).await?;
+    }
+    "/unalias" => {
+      let reply = if args.is_empty() {
+        "Usage: /unalias <from>".to_string()
+      } else if remove_alias(msg.chat.id, args) {
+        save_aliases();
+        format!("Removed the alias for \"{}\".", args)
+      } else {
+        format!("There is no alias for \"{}\".", args)
+      };
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, reply).await?;
+    }
+    "/preview" => {
+      // Each preview is a full search that skips the throttle and circuit breaker
+      if is_admin(msg.from.as_ref()) {
+        if args.is_empty() {
+          send_gate::acquire().await;
+          bot
+            .send_message(msg.chat.id, "Usage: /preview <query>")
+            .await?;
+        } else {
+          send_preview(bot, msg.chat.id, args, &user_locale(msg.from.as_ref())).await?;
+        }
+      }
+    }
+    "/test" => {
+      // Makes the bot fetch an arbitrary URL, so only admins may use it
+      if is_admin(msg.from.as_ref()) {
+        let reply = if args.is_empty() {
+          "Usage: /test <image URL>".to_string()
+        } else {
+          test_send(bot, msg.chat.id, args).await
+        };
+        send_gate::acquire().await;
+        bot.send_message(msg.chat.id, reply).await?;
+      }
+    }
+    "/which" => {
+      let reply = if args.is_empty() {
+        "Usage: /which <query>".to_string()
+      } else {
+        describe_query(msg.chat.id, args)?
+      };
+      send_gate::acquire().await;
+      bot.send_message(msg.chat.id, reply).await?;
+    }
+    "/grid" => {
+      with_handler_timeout(
+        msg.chat.id,
+        handler_timeout(),
+        &format!("building grid for '{}'", args),
+        send_grid(bot, msg.chat.id, args, &user_locale(msg.from.as_ref())),
+      )
+      .await;
+    }
+    _ => return Ok(false),
+  }
+
+  Ok(true)
+}
+
+// Reload the config file and summarize what changed
+fn describe_reload() -> String {
+  match reload_config_file() {
+    Ok(reload) => {
+      let mut lines = vec![format!("Reloaded {}", reload.path)];
+      if reload.applied.is_empty() && reload.needs_restart.is_empty() {
+        lines.push("No settings changed.".to_string());
+      }
+      if !reload.applied.is_empty() {
+        lines.push(format!("Applied: {}", reload.applied.join(", ")));
+      }
+      if !reload.needs_restart.is_empty() {
+        lines.push(format!(
+          "Changed but need a restart: {}",
+          reload.needs_restart.join(", ")
+        ));
+      }
+      lines.join("\n")
+    }
+    Err(e) => format!("Could not reload config: {}", e),
+  }
+}
+
+// Current load: updates being handled, searches waiting on engines, sends waiting on the gate
+fn describe_queue() -> String {
+  let (waiting_sends, backlog) = send_gate::backlog();
+  let online = match circuit_state() {
+    Circuit::Closed { .. } => "running".to_string(),
+    Circuit::Open { until } => format!(
+      "paused for {}s",
+      until.saturating_duration_since(Instant::now()).as_secs()
+    ),
+    Circuit::HalfOpen { .. } => "probing".to_string(),
+  };
+  format!(
+    "Updates in flight: {}\nSearches in flight: {}\nSends waiting: {} ({} ms backlog)\nOnline search: {}",
+    in_flight(),
+    searches_in_flight(),
+    waiting_sends,
+    backlog.as_millis(),
+    online
+  )
+}
+
+// Recent per-engine success rates, to spot an engine whose results page changed,
+// followed by response times per branch to tell slow scraping from slow sends
+fn describe_stats() -> String {
+  let rates = success_rates();
+  let mut lines: Vec<_> = rates
+    .iter()
+    .map(|(engine, samples, rate)| {
+      format!(
+        "{}: {:.0}% of the last {} searches succeeded",
+        engine.label(),
+        rate,
+        samples
+      )
+    })
+    .collect();
+  if rates.is_empty() {
+    lines.push("No searches recorded yet.".to_string());
+  }
+
+  let response_times = describe_response_times();
+  if !response_times.is_empty() {
+    lines.push(String::new());
+    lines.push("Response times:".to_string());
+    lines.extend(response_times);
+  }
+  lines.join("\n")
+}
+
+// Usage plus the chat's current aliases, for a bare or malformed /alias
+fn describe_aliases(chat_id: ChatId) -> String {
+  let aliases = aliases(chat_id);
+  if aliases.is_empty() {
+    return "Usage: /alias <from> = <to>\nNo aliases set in this chat.".to_string();
+  }
+
+  let lines: Vec<_> = aliases
+    .iter()
+    .map(|(from, to)| format!("{} = {}", from, to))
+    .collect();
+  format!(
+    "Usage: /alias <from> = <to>\nAliases in this chat:\n{}",
+    lines.join("\n")
+  )
+}
+
+// Reply with the candidate URLs a search would try, without sending any images
+async fn send_preview(
+  bot: &Bot,
+  chat_id: ChatId,
+  text: &str,
+  locale: &SearchLocale,
+) -> Result<(), anyhow::Error> {
+  let (query, is_gif) = parse_trigger(text)?.unwrap_or_else(|| (text.to_string(), false));
+  let resolved = resolve_alias(chat_id, &strip_stopwords(&query, stopwords()));
+  if refuse_banned(bot, chat_id, &[&query, &resolved]).await? {
+    return Ok(());
+  }
+  let query = resolved;
+
+  let mut lines = Vec::new();
+  if let Some(image_url) = find_featured(&query, featured()) {
+    lines.push(format!("★ [featured] {}", image_url));
+  }
+  match image_search(&query, is_gif, locale).await {
+    Ok(results) => {
+      lines.extend(results.iter().enumerate().map(|(index, result)| {
+        format!("{}. [{}] {}", index + 1, result.engine.label(), result.url)
+      }))
+    }
+    Err(e) => lines.push(format!("Search failed: {}", e)),
+  }
+
+  let mut body = String::new();
+  for line in lines {
+    if body.chars().count() + line.chars().count() > MAX_PREVIEW_CHARS {
+      body.push('…');
+      break;
+    }
+    body.push_str(&line);
+    body.push('\n');
+  }
+
+  send_gate::acquire().await;
+  bot
+    .send_message(
+      chat_id,
+      format!(
+        "Candidates for \"{}\" ({}):\n{}",
+        query,
+        media_kind(is_gif),
+        body.trim_end()
+      ),
+    )
+    .link_preview_options(no_link_preview())
+    .await?;
+
+  Ok(())
+}
+
+// Explain each step that turns user text into the query sent to the search engines
+fn describe_query(chat_id: ChatId, text: &str) -> Result<String, anyhow::Error> {
+  let (query, is_gif, trigger) = match parse_trigger(text)? {
+    Some((query, is_gif)) => {
+      let trigger = format!("\"{}\"", query);
+      (query, is_gif, trigger)
+    }
+    None => (
+      text.to_string(),
+      false,
+      "no .jpg/.png/.gif suffix; whole text is used".to_string(),
+    ),
+  };
+  let stripped = strip_stopwords(&query, stopwords());
+  let aliased = resolve_alias(chat_id, &stripped);
+
+  Ok(format!(
+    "Input: \"{}\"\nTrigger: {}\nAfter stopwords: \"{}\"\nAfter aliases: \"{}\"\nSearch type: {}",
+    text,
+    trigger,
+    stripped,
+    aliased,
+    if is_gif { "GIF" } else { "image" }
+  ))
+}
+
+// URL lists read better without a preview of the first link
+fn no_link_preview() -> LinkPreviewOptions {
+  LinkPreviewOptions {
+    is_disabled: true,
+    url: None,
+    prefer_small_media: false,
+    prefer_large_media: false,
+    show_above_text: false,
+  }
+}
+
+// Send one URL through the normal routing so a new featured pin can be checked,
+// returning Telegram's error verbatim on failure
+async fn test_send(bot: &Bot, chat_id: ChatId, image_url: &str) -> String {
+  let Ok(url) = Url::parse(image_url) else {
+    return "That isn't a valid URL.".to_string();
+  };
+  if !matches!(url.scheme(), "http" | "https") {
+    return "Only http and https URLs can be tested.".to_string();
+  }
+  let is_gif = url.path().to_lowercase().ends_with(".gif");
+  let method = send_method_for(image_url, is_gif, send_method_overrides());
+  match send_image(bot, chat_id, None, image_url, is_gif, None).await {
+    Ok(()) => format!("Sent as {}.", method.label()),
+    Err(e) => format!("Failed to send as {}: {:#}", method.label(), e),
+  }
+}
+
+// Summarize images that failed to decode since startup, newest first
+fn describe_decode_failures() -> String {
+  let (total, recent) = decode_failures();
+  if total == 0 {
+    return "No problematic images so far.".to_string();
+  }
+
+  let mut body = format!(
+    "{} image(s) could not be decoded since startup. Most recent:\n",
+    total
+  );
+  for source in recent.iter().rev() {
+    if body.len() + source.len() + 1 > MAX_PREVIEW_CHARS {
+      body.push('…');
+      break;
+    }
+    body.push_str(source);
+    body.push('\n');
+  }
+  body
+}
+
+// Download the top matches and send them as one contact-sheet image
+async fn send_grid(
+  bot: &Bot,
+  chat_id: ChatId,
+  query: &str,
+  locale: &SearchLocale,
+) -> Result<(), anyhow::Error> {
+  if query.is_empty() {
+    send_gate::acquire().await;
+    bot.send_message(chat_id, "Usage: /grid <query>").await?;
+    return Ok(());
+  }
+
+  if refuse_banned(bot, chat_id, &[query]).await? {
+    return Ok(());
+  }
+  let query = strip_stopwords(query, stopwords());
+  let results = image_search(&query, false, locale).await?;
+  let grid_size = grid_size();
+
+  let mut images = Vec::new();
+  for search_result in results.iter() {
+    if images.len() >= grid_size {
+      break;
+    }
+
+    match download_image(&search_result.url).await {
+      Ok(data) => images.push((search_result.url.clone(), data)),
+      Err(e) => {
+        error!("Failed to download image {}: {:?}", search_result.url, e);
+        record_error(
+          chat_id,
+          format!("failed to download {}: {:#}", search_result.url, e),
+        );
+      }
+    }
+  }
+
+  if images.is_empty() {
+    send_gate::acquire().await;
+    bot
+      .send_message(chat_id, format!("No images found for \"{}\"", query))
+      .await?;
+    return Ok(());
+  }
+
+  let montage = build_montage_blocking(images).await?;
+  send_gate::acquire().await;
+  bot
+    .send_photo(chat_id, InputFile::memory(montage).file_name("grid.jpg"))
+    .await?;
+  info!("Sent grid for query '{}'", query);
+
+  Ok(())
+}
+
+// Read GRID_SIZE (number of images in /grid), capped at MAX_GRID_IMAGES
+fn grid_size() -> usize {
+  setting("GRID_SIZE")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .filter(|size| *size > 0)
+    .unwrap_or(DEFAULT_GRID_SIZE)
+    .min(MAX_GRID_IMAGES)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use tgjpg_rs::image_search::SearchEngine;
+
+  #[test]
+  fn count_suffix_is_split_off() {
+    assert_eq!(split_count_suffix("cat.jpg x3"), ("cat.jpg", Some(3)));
+    assert_eq!(split_count_suffix("cat.jpg ×2"), ("cat.jpg", Some(2)));
+  }
+
+  #[test]
+  fn count_is_capped_at_one_media_group() {
+    assert_eq!(
+      split_count_suffix("cat.jpg x50"),
+      ("cat.jpg", Some(MAX_ALBUM_SIZE))
+    );
+  }
+
+  #[test]
+  fn zero_or_missing_count_leaves_text_alone() {
+    assert_eq!(split_count_suffix("cat.jpg x0"), ("cat.jpg x0", None));
+    assert_eq!(split_count_suffix("cat.jpg"), ("cat.jpg", None));
+    assert_eq!(split_count_suffix("x ray.jpg"), ("x ray.jpg", None));
+  }
+
+  #[tokio::test]
+  async fn handler_timeout_gives_up_and_records_the_error() {
+    tokio::time::pause();
+    let chat_id = ChatId(625);
+
+    let output = with_handler_timeout(
+      chat_id,
+      Duration::from_secs(30),
+      "handling 'cats'",
+      std::future::pending::<()>(),
+    )
+    .await;
+
+    assert_eq!(output, None);
+    let (_, detail) = last_error(chat_id).unwrap();
+    assert_eq!(detail, "timed out after 30s handling 'cats'");
+  }
+
+  fn edited_message(message_id: i32, text: &str, edit_date: i64) -> Message {
+    serde_json::from_value(serde_json::json!({
+      "message_id": message_id,
+      "date": edit_date - 5,
+      "edit_date": edit_date,
+      "chat": { "id": 633, "type": "private", "first_name": "Ada" },
+      "text": text
+    }))
+    .unwrap()
+  }
+
+  #[test]
+  fn fresh_edit_into_a_trigger_fires_the_search() {
+    let msg = edited_message(1, "cat.jpg", 1_000);
+    assert!(is_fresh_unanswered_edit(&msg, 1_002));
+    assert_eq!(
+      parse_trigger(msg.text().unwrap()).unwrap(),
+      Some(("cat".to_string(), false))
+    );
+  }
+
+  #[test]
+  fn stale_or_answered_edits_are_ignored() {
+    let stale = edited_message(2, "cat.jpg", 1_000);
+    assert!(!is_fresh_unanswered_edit(
+      &stale,
+      1_000 + EDIT_MAX_AGE_SECS + 1
+    ));
+
+    let answered = edited_message(3, "cat.jpg", 1_000);
+    mark_responded(answered.chat.id, answered.id);
+    assert!(!is_fresh_unanswered_edit(&answered, 1_002));
+  }
+
+  #[tokio::test]
+  async fn channel_posts_reach_the_trigger_path() {
+    // Parsed from text: the flattened update kind does not survive from_value
+    let update: Update = serde_json::from_str(
+      r#"{
+        "update_id": 1,
+        "channel_post": {
+          "message_id": 7,
+          "date": 0,
+          "chat": { "id": -1001, "type": "channel", "title": "Pics" },
+          "text": "cat.jpg"
+        }
+      }"#,
+    )
+    .unwrap();
+
+    let handler = Update::filter_channel_post().endpoint(|msg: Message| async move { msg });
+    let msg = match handler.dispatch(dptree::deps![update]).await {
+      std::ops::ControlFlow::Break(msg) => msg,
+      std::ops::ControlFlow::Continue(_) => panic!("channel post was not routed"),
+    };
+
+    // Channel posts have no sender, which must not block the search
+    assert!(user_allowed(msg.from.as_ref()));
+    assert_eq!(
+      parse_trigger(msg.text().unwrap()).unwrap(),
+      Some(("cat".to_string(), false))
+    );
+  }
+
+  #[test]
+  fn send_route_picks_method_and_download() {
+    let overrides = HashMap::from([("webp".to_string(), SendMethod::Sticker)]);
+    let domains = vec!["imgur.com".to_string()];
+
+    assert_eq!(
+      send_route("https://i.imgur.com/cat.jpg", false, &overrides, &domains),
+      (SendMethod::Photo, true)
+    );
+    assert_eq!(
+      send_route("https://example.com/dance.gif", true, &overrides, &domains),
+      (SendMethod::Animation, false)
+    );
+    assert_eq!(
+      send_route("https://example.com/cat.webp", false, &overrides, &domains),
+      (SendMethod::Sticker, false)
+    );
+  }
+
+  fn captioned_photo(is_bot: bool) -> Message {
+    serde_json::from_value(serde_json::json!({
+      "message_id": 1,
+      "date": 0,
+      "chat": { "id": 642, "type": "private", "first_name": "Ada" },
+      "from": { "id": 642, "is_bot": is_bot, "first_name": "Ada" },
+      "photo": [{ "file_id": "photo", "file_unique_id": "photo", "width": 90, "height": 90 }],
+      "caption": "cat.jpg"
+    }))
+    .unwrap()
+  }
+
+  #[test]
+  fn caption_trigger_fires_the_search() {
+    let msg = captioned_photo(false);
+    assert_eq!(
+      trigger_text(&msg).map(parse_trigger).transpose().unwrap(),
+      Some(Some(("cat".to_string(), false)))
+    );
+  }
+
+  #[test]
+  fn captions_from_bots_are_ignored() {
+    assert_eq!(trigger_text(&captioned_photo(true)), None);
+  }
+
+  #[test]
+  fn over_long_trigger_is_ignored_before_matching() {
+    let long = format!("{}.jpg", "a".repeat(MAX_QUERY_CHARS));
+    assert!(is_too_long(&long));
+    assert_eq!(parse_trigger(&long).unwrap(), None);
+
+    let fits = format!("{}.jpg", "a".repeat(MAX_QUERY_CHARS - 4));
+    assert!(!is_too_long(&fits));
+    assert!(parse_trigger(&fits).unwrap().is_some());
+  }
+
+  #[test]
+  fn reply_parameters_point_at_the_trigger() {
+    let parameters = reply_parameters(MessageId(42));
+    assert_eq!(parameters.message_id, MessageId(42));
+    assert_eq!(parameters.allow_sending_without_reply, Some(true));
+  }
+
+  // Lowercased method names, or request bodies, in the order the mock received them
+  type Recorded = Arc<Mutex<Vec<String>>>;
+
+  // A stand-in for the Bot API on localhost. Each request is answered with `respond(method)`,
+  // where method is the lowercased API method name, and the methods called are recorded.
+  fn mock_bot(respond: impl Fn(&str) -> String + Send + 'static) -> (Bot, Recorded) {
+    let (bot, calls, _) = mock_bot_with_bodies(respond);
+    (bot, calls)
+  }
+
+  // Like mock_bot, but also keeps each request body for tests that check what was sent
+  fn mock_bot_with_bodies(
+    respond: impl Fn(&str) -> String + Send + 'static,
+  ) -> (Bot, Recorded, Recorded) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    let (recorded, recorded_bodies) = (Arc::clone(&calls), Arc::clone(&bodies));
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+          continue;
+        };
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let (mut content_length, mut chunked) = (0, false);
+        loop {
+          let mut header = String::new();
+          reader.read_line(&mut header).unwrap();
+          if header.trim().is_empty() {
+            break;
+          }
+          if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+              content_length = value.trim().parse().unwrap();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+              chunked = value.trim().eq_ignore_ascii_case("chunked");
+            }
+          }
+        }
+        // Read the whole body so closing the connection doesn't reset it mid-response
+        let mut request_body = Vec::new();
+        if chunked {
+          loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).unwrap();
+            let size = u64::from_str_radix(size.trim(), 16).unwrap();
+            reader
+              .by_ref()
+              .take(size + 2)
+              .read_to_end(&mut request_body)
+              .unwrap();
+            // Drop the CRLF that ends each chunk
+            request_body.truncate(request_body.len() - 2);
+            if size == 0 {
+              break;
+            }
+          }
+        } else {
+          reader
+            .by_ref()
+            .take(content_length)
+            .read_to_end(&mut request_body)
+            .unwrap();
+        }
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let method = path.rsplit('/').next().unwrap_or_default().to_lowercase();
+        let body = respond(&method);
+        recorded.lock().unwrap().push(method);
+        recorded_bodies
+          .lock()
+          .unwrap()
+          .push(String::from_utf8_lossy(&request_body).into_owned());
+        write!(
+          stream,
+          "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        )
+        .unwrap();
+      }
+    });
+
+    let client = teloxide::net::default_reqwest_settings()
+      .no_proxy()
+      .build()
+      .unwrap();
+    (
+      Bot::with_client("123:mock", client).set_api_url(api_url),
+      calls,
+      bodies,
+    )
+  }
+
+  fn sent_message(media: &str) -> String {
+    serde_json::json!({
+      "ok": true,
+      "result": {
+        "message_id": 1,
+        "date": 0,
+        "chat": { "id": 1, "type": "private", "first_name": "Ada" },
+        (media): {
+          "file_id": "file",
+          "file_unique_id": "file",
+          "width": 1,
+          "height": 1,
+          "duration": 1
+        }
+      }
+    })
+    .to_string()
+  }
+
+  fn rejected() -> String {
+    serde_json::json!({
+      "ok": false,
+      "error_code": 400,
+      "description": "Bad Request: wrong file type"
+    })
+    .to_string()
+  }
+
+  #[tokio::test]
+  async fn rejected_webp_animation_is_sent_as_document() {
+    let (bot, calls) = mock_bot(|method| match method {
+      "sendanimation" => rejected(),
+      _ => sent_message("document"),
+    });
+    let url = "https://example.com/dance.webp";
+
+    let (_, sent) = send_input_file(
+      &bot,
+      ChatId(1),
+      None,
+      url,
+      SendMethod::Animation,
+      InputFile::url(Url::parse(url).unwrap()),
+      None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(sent, SendMethod::Document);
+    assert_eq!(*calls.lock().unwrap(), ["sendanimation", "senddocument"]);
+  }
+
+  #[tokio::test]
+  async fn gif_loop_sends_the_wanted_count_past_failures() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&attempts);
+    // The second and fourth candidates are rejected
+    let (bot, calls) = mock_bot(move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+      1 | 3 => rejected(),
+      _ => sent_message("animation"),
+    });
+    let candidates: Vec<SearchResult> = (1..=6)
+      .map(|n| SearchResult {
+        url: format!("https://example.com/dance-{}.gif", n),
+        engine: SearchEngine::Ddg,
+      })
+      .collect();
+
+    let sent = send_candidates(&bot, ChatId(694), None, &candidates, 3, true, None).await;
+
+    assert_eq!(sent, Some(3));
+    assert_eq!(calls.lock().unwrap().len(), 5);
+    assert!(calls
+      .lock()
+      .unwrap()
+      .iter()
+      .all(|method| method == "sendanimation"));
+  }
+
+  #[tokio::test]
+  async fn link_is_sent_when_every_image_send_fails() {
+    let (bot, calls) = mock_bot(|method| match method {
+      "sendmessage" => serde_json::json!({
+        "ok": true,
+        "result": {
+          "message_id": 2,
+          "date": 0,
+          "chat": { "id": 1, "type": "private", "first_name": "Ada" },
+          "text": "Couldn't send the image"
+        }
+      })
+      .to_string(),
+      _ => rejected(),
+    });
+    let candidates: Vec<SearchResult> = ["first", "second"]
+      .iter()
+      .map(|name| SearchResult {
+        url: format!("https://example.com/{}.jpg", name),
+        engine: SearchEngine::Ddg,
+      })
+      .collect();
+
+    let sent = send_candidates(&bot, ChatId(702), None, &candidates, 1, false, None).await;
+    assert_eq!(sent, Some(0));
+    send_last_resort(&bot, ChatId(702), None, candidates.first(), true).await;
+
+    assert_eq!(
+      *calls.lock().unwrap(),
+      ["sendphoto", "sendphoto", "sendmessage"]
+    );
+    assert!(last_error(ChatId(702)).is_some());
+  }
+
+  #[test]
+  fn jpeg_suffix_triggers_a_photo_search() {
+    assert_eq!(
+      parse_trigger("something.JPEG").unwrap(),
+      Some(("something".to_string(), false))
+    );
+    assert_eq!(
+      send_route(
+        "https://example.com/something.jpeg",
+        false,
+        &HashMap::new(),
+        &[]
+      ),
+      (SendMethod::Photo, false)
+    );
+  }
+
+  #[tokio::test]
+  async fn unknown_slash_trigger_falls_through_to_search() {
+    let (bot, calls) = mock_bot(|_| {
+      serde_json::json!({
+        "ok": true,
+        "result": {
+          "message_id": 2,
+          "date": 0,
+          "chat": { "id": 716, "type": "private", "first_name": "Ada" },
+          "text": "help"
+        }
+      })
+      .to_string()
+    });
+    let bot_info = BotInfo {
+      username: "tgjpg_bot".to_string(),
+    };
+    let message = |text: &str| -> Message {
+      serde_json::from_value(serde_json::json!({
+        "message_id": 1,
+        "date": 0,
+        "chat": { "id": 716, "type": "private", "first_name": "Ada" },
+        "text": text
+      }))
+      .unwrap()
+    };
+
+    let unknown = message("/notacommand.jpg");
+    assert!(!handle_command(&bot, &unknown, &bot_info).await.unwrap());
+    assert!(calls.lock().unwrap().is_empty());
+    assert_eq!(
+      parse_trigger(unknown.text().unwrap()).unwrap(),
+      Some(("/notacommand".to_string(), false))
+    );
+
+    // Real commands are still answered and never searched
+    assert!(handle_command(&bot, &message("/help"), &bot_info)
+      .await
+      .unwrap());
+    assert_eq!(*calls.lock().unwrap(), ["sendmessage"]);
+  }
+
+  #[tokio::test]
+  async fn cached_file_id_is_reused_then_dropped_when_rejected() {
+    let responses = AtomicUsize::new(0);
+    let (bot, calls) = mock_bot(move |_| {
+      let photo = |file_id: &str| {
+        serde_json::json!({
+          "ok": true,
+          "result": {
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": 1, "type": "private", "first_name": "Ada" },
+            "photo": [{ "file_id": file_id, "file_unique_id": file_id, "width": 1, "height": 1 }]
+          }
+        })
+        .to_string()
+      };
+      match responses.fetch_add(1, Ordering::SeqCst) {
+        0 => photo("uploaded"),
+        1 => photo("uploaded"),
+        2 => serde_json::json!({
+          "ok": false,
+          "error_code": 400,
+          "description": "Bad Request: wrong file identifier/HTTP URL specified"
+        })
+        .to_string(),
+        _ => photo("uploaded-again"),
+      }
+    });
+    let url = "https://example.com/file-id-reuse.jpg";
+    let cached = || cached_file_id(bot_id(&bot), url, SendMethod::Photo);
+
+    send_image(&bot, ChatId(7321), None, url, false, None)
+      .await
+      .unwrap();
+    assert_eq!(cached(), Some((SendMethod::Photo, "uploaded".to_string())));
+
+    // Another chat is served from the file ID
+    send_image(&bot, ChatId(7322), None, url, false, None)
+      .await
+      .unwrap();
+
+    // A rejected file ID is forgotten and the URL is sent again
+    send_image(&bot, ChatId(7323), None, url, false, None)
+      .await
+      .unwrap();
+    assert_eq!(
+      cached(),
+      Some((SendMethod::Photo, "uploaded-again".to_string()))
+    );
+    assert_eq!(calls.lock().unwrap().len(), 4);
+  }
+
+  #[tokio::test]
+  async fn maintenance_suppresses_searches_and_commands() {
+    let (bot, calls) = mock_bot(|_| {
+      serde_json::json!({
+        "ok": true,
+        "result": {
+          "message_id": 2,
+          "date": 0,
+          "chat": { "id": 733, "type": "private", "first_name": "Ada" },
+          "text": "The bot is under maintenance, please try again later."
+        }
+      })
+      .to_string()
+    });
+    let message = |text: &str| -> Message {
+      serde_json::from_value(serde_json::json!({
+        "message_id": 1,
+        "date": 0,
+        "chat": { "id": 733, "type": "private", "first_name": "Ada" },
+        "from": { "id": 733, "is_bot": false, "first_name": "Ada" },
+        "text": text
+      }))
+      .unwrap()
+    };
+
+    let search = message("cat.jpg");
+    let command = message("/help");
+    assert!(held_for_maintenance(&bot, &search, "cat.jpg", true, true)
+      .await
+      .unwrap());
+    assert!(held_for_maintenance(&bot, &command, "/help", true, true)
+      .await
+      .unwrap());
+    assert!(!held_for_maintenance(&bot, &command, "/help", false, true)
+      .await
+      .unwrap());
+
+    // The command gets the maintenance notice and the search is dropped
+    assert_eq!(*calls.lock().unwrap(), ["sendmessage"]);
+  }
+
+  #[tokio::test]
+  async fn online_result_carries_the_query_caption() {
+    let (bot, calls, bodies) = mock_bot_with_bodies(|_| sent_message("animation"));
+    let chat_id = ChatId(735);
+    assert!(!query_caption_enabled(chat_id));
+    set_query_caption_enabled(chat_id, true);
+
+    let caption = query_caption_enabled(chat_id).then_some("dancing cat");
+    let url = "https://example.com/query-caption.gif";
+    send_image(&bot, chat_id, None, url, true, caption)
+      .await
+      .unwrap();
+
+    assert_eq!(*calls.lock().unwrap(), ["sendanimation"]);
+    let body = &bodies.lock().unwrap()[0];
+    assert!(body.contains("name=\"caption\""));
+    assert!(body.contains("dancing cat"));
+  }
+
+  #[tokio::test]
+  async fn blocked_bot_aborts_the_remaining_candidates() {
+    let (bot, calls) = mock_bot(|_| {
+      serde_json::json!({
+        "ok": false,
+        "error_code": 403,
+        "description": "Forbidden: bot was blocked by the user"
+      })
+      .to_string()
+    });
+    let candidates: Vec<SearchResult> = ["first", "second", "third"]
+      .iter()
+      .map(|name| SearchResult {
+        url: format!("https://example.com/blocked-{}.jpg", name),
+        engine: SearchEngine::Google,
+      })
+      .collect();
+
+    let sent = send_candidates(&bot, ChatId(736), None, &candidates, 1, false, None).await;
+
+    assert_eq!(sent, None);
+    assert_eq!(*calls.lock().unwrap(), ["sendphoto"]);
+  }
+
+  #[test]
+  fn hashtag_triggers_a_search_without_a_suffix() {
+    let chat_id = ChatId(680);
+    let msg: Message = serde_json::from_value(serde_json::json!({
+      "message_id": 1,
+      "date": 0,
+      "chat": { "id": 680, "type": "supergroup", "title": "Memes" },
+      "from": { "id": 680, "is_bot": false, "first_name": "Ada" },
+      "text": "lol #anon_happy",
+      "entities": [{ "type": "hashtag", "offset": 4, "length": 11 }]
+    }))
+    .unwrap();
+    assert_eq!(parse_trigger(msg.text().unwrap()).unwrap(), None);
+    assert_eq!(hashtag_query(&msg), None);
+
+    set_hashtags_enabled(chat_id, true);
+
+    assert_eq!(hashtag_query(&msg).as_deref(), Some("anon happy"));
+  }
+}
@@ -0,0 +1,12 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static MAINTENANCE: AtomicBool = AtomicBool::new(false);
+
+// While on, the bot ignores searches everywhere; toggled by the admin with /maintenance
+pub fn maintenance_enabled() -> bool {
+  MAINTENANCE.load(Ordering::SeqCst)
+}
+
+pub fn set_maintenance(enabled: bool) {
+  MAINTENANCE.store(enabled, Ordering::SeqCst);
+}
@@ -0,0 +1,200 @@
+use crate::config_file::setting;
+
+const DEFAULT_WELCOME: &str =
+  "Welcome! Send me a message like \"cat.jpg\" or \"dog.gif\" to search for images.\n\
+   You can also use me in any chat by typing @botname followed by your search term!";
+
+const DEFAULT_HELP: &str =
+  "Send a message ending in .jpg, .jpeg, .png or .gif (e.g. \"cat.jpg\") to get an image.\n\
+   Add a count to get several at once, e.g. \"cat.jpg x3\" (up to 10).\n\
+   Inline: type @botname followed by your search term in any chat.\n\n\
+   Commands:\n\
+   /start - Show the welcome message\n\
+   /help - Show this help\n\
+   /cancel - Cancel any multi-step interaction in progress\n\
+   /which <query> - Show how a query is interpreted before searching\n\
+   /grid <query> - Get the top matches combined into one image\n\
+   /reply_on, /reply_off - Toggle sending images as replies in this chat\n\
+   /order relevance|shuffle - Send the best match or a random one in this chat\n\
+   /hashtag_on, /hashtag_off - Toggle searching for #hashtags in this chat\n\
+   /querycaption_on, /querycaption_off - Toggle captioning images with the search query\n\
+   /alias <from> = <to> - Make one query search for another in this chat\n\
+   /unalias <from> - Remove an alias\n\
+   /stats - Show recent success rates of each search engine\n\
+   /feedback <text> - Send feedback to the bot operator";
+
+const DEFAULT_WELCOME_ZH_TW: &str = "歡迎！傳送像「cat.jpg」或「dog.gif」這樣的訊息來搜尋圖片。\n\
+   你也可以在任何聊天室輸入 @botname 加上關鍵字來使用我！";
+
+const DEFAULT_HELP_ZH_TW: &str =
+  "傳送以 .jpg、.jpeg、.png 或 .gif 結尾的訊息（例如「cat.jpg」）來取得圖片。\n\
+   加上數量可一次取得多張，例如「cat.jpg x3」（最多 10 張）。\n\
+   Inline 模式：在任何聊天室輸入 @botname 加上關鍵字。\n\n\
+   指令：\n\
+   /start - 顯示歡迎訊息\n\
+   /help - 顯示這份說明\n\
+   /cancel - 取消進行中的多步驟操作\n\
+   /which <關鍵字> - 顯示搜尋前關鍵字如何被解讀\n\
+   /grid <關鍵字> - 把最相符的幾張圖合成一張\n\
+   /reply_on, /reply_off - 切換本聊天室是否以回覆方式傳送圖片\n\
+   /order relevance|shuffle - 本聊天室傳送最相符或隨機的結果\n\
+   /hashtag_on, /hashtag_off - 切換本聊天室是否搜尋 #hashtag\n\
+   /querycaption_on, /querycaption_off - 切換圖片是否附上搜尋關鍵字說明\n\
+   /alias <原詞> = <替代詞> - 在本聊天室把一個關鍵字改搜另一個\n\
+   /unalias <原詞> - 移除替代詞\n\
+   /stats - 顯示各搜尋引擎最近的成功率\n\
+   /feedback <內容> - 傳送意見給機器人管理員";
+
+// Languages with built-in texts; everything else gets English
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageLocale {
+  English,
+  TraditionalChinese,
+}
+
+impl MessageLocale {
+  // Pick from a Telegram language_code such as "zh-hant" or "en"
+  pub fn from_language_code(language_code: Option<&str>) -> Self {
+    match language_code.map(|code| code.trim().to_lowercase()) {
+      Some(code) if code == "zh" || code.starts_with("zh-") => MessageLocale::TraditionalChinese,
+      _ => MessageLocale::English,
+    }
+  }
+
+  // Suffix for per-language overrides, e.g. WELCOME_MESSAGE_ZH_TW
+  fn env_suffix(self) -> &'static str {
+    match self {
+      MessageLocale::English => "EN",
+      MessageLocale::TraditionalChinese => "ZH_TW",
+    }
+  }
+}
+
+// Text for /start: WELCOME_MESSAGE_<LANG> or WELCOME_MESSAGE overrides the default,
+// PROJECT_URL adds a link for forks
+pub fn welcome_message(locale: MessageLocale) -> String {
+  let default = match locale {
+    MessageLocale::English => DEFAULT_WELCOME,
+    MessageLocale::TraditionalChinese => DEFAULT_WELCOME_ZH_TW,
+  };
+  with_project_url(
+    localized_override("WELCOME_MESSAGE", locale).unwrap_or_else(|| default.to_string()),
+    locale,
+  )
+}
+
+// Text for /help: HELP_MESSAGE_<LANG> or HELP_MESSAGE overrides the default
+pub fn help_message(locale: MessageLocale) -> String {
+  let default = match locale {
+    MessageLocale::English => DEFAULT_HELP,
+    MessageLocale::TraditionalChinese => DEFAULT_HELP_ZH_TW,
+  };
+  with_project_url(
+    localized_override("HELP_MESSAGE", locale).unwrap_or_else(|| default.to_string()),
+    locale,
+  )
+}
+
+fn localized_override(key: &str, locale: MessageLocale) -> Option<String> {
+  pick_override(key, locale, |name| setting(name).ok())
+}
+
+// The per-language value wins over the generic one. Env values can't easily hold
+// newlines, so a literal "\n" is accepted as a line break.
+fn pick_override(
+  key: &str,
+  locale: MessageLocale,
+  lookup: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+  let read = |name: &str| {
+    lookup(name)
+      .map(|value| value.replace("\\n", "\n"))
+      .filter(|value| !value.trim().is_empty())
+  };
+  read(&format!("{}_{}", key, locale.env_suffix())).or_else(|| read(key))
+}
+
+fn with_project_url(text: String, locale: MessageLocale) -> String {
+  match setting("PROJECT_URL") {
+    Ok(url) if !url.trim().is_empty() => match locale {
+      MessageLocale::English => format!("{}\nSee {} for more information.", text, url.trim()),
+      MessageLocale::TraditionalChinese => format!("{}\n更多資訊請見 {}", text, url.trim()),
+    },
+    _ => text,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::collections::HashMap;
+
+  fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+      .iter()
+      .map(|(key, value)| (key.to_string(), value.to_string()))
+      .collect();
+    move |name| vars.get(name).cloned()
+  }
+
+  #[test]
+  fn help_override_replaces_the_default() {
+    let vars = lookup(&[("HELP_MESSAGE_EN", "My bot\\nSend cat.jpg")]);
+    assert_eq!(
+      pick_override("HELP_MESSAGE", MessageLocale::English, &vars).as_deref(),
+      Some("My bot\nSend cat.jpg")
+    );
+    assert_eq!(
+      pick_override("WELCOME_MESSAGE", MessageLocale::English, &vars),
+      None
+    );
+    // Blank values don't blank out the default
+    let blank = lookup(&[("HELP_MESSAGE", "  ")]);
+    assert_eq!(
+      pick_override("HELP_MESSAGE", MessageLocale::English, blank),
+      None
+    );
+  }
+
+  #[test]
+  fn locale_follows_the_language_code() {
+    assert_eq!(
+      MessageLocale::from_language_code(Some("zh-hant")),
+      MessageLocale::TraditionalChinese
+    );
+    assert_eq!(
+      MessageLocale::from_language_code(Some("ZH")),
+      MessageLocale::TraditionalChinese
+    );
+    assert_eq!(
+      MessageLocale::from_language_code(Some("en")),
+      MessageLocale::English
+    );
+    // Languages without texts, and users without a language, fall back to English
+    assert_eq!(
+      MessageLocale::from_language_code(Some("ja")),
+      MessageLocale::English
+    );
+    assert_eq!(
+      MessageLocale::from_language_code(None),
+      MessageLocale::English
+    );
+
+    assert!(welcome_message(MessageLocale::TraditionalChinese).starts_with("歡迎"));
+    assert!(welcome_message(MessageLocale::English).starts_with("Welcome"));
+  }
+
+  #[test]
+  fn generic_override_covers_languages_without_their_own() {
+    let vars = lookup(&[("HELP_MESSAGE", "hello"), ("HELP_MESSAGE_ZH_TW", "你好")]);
+    assert_eq!(
+      pick_override("HELP_MESSAGE", MessageLocale::TraditionalChinese, &vars).as_deref(),
+      Some("你好")
+    );
+    assert_eq!(
+      pick_override("HELP_MESSAGE", MessageLocale::English, &vars).as_deref(),
+      Some("hello")
+    );
+  }
+}
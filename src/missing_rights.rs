@@ -0,0 +1,40 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use teloxide::types::ChatId;
+use teloxide::{ApiError, RequestError};
+
+const NOTIFIED_CHATS_CAPACITY: usize = 1000;
+static NOTIFIED_CHATS: OnceLock<Mutex<VecDeque<ChatId>>> = OnceLock::new();
+
+// Whether a send failed because the bot is restricted from posting this kind of media,
+// e.g. "Bad Request: not enough rights to send photos to the chat"
+pub fn is_missing_rights(error: &anyhow::Error) -> bool {
+  match error.downcast_ref::<RequestError>() {
+    Some(RequestError::Api(ApiError::NotEnoughRightsToPostMessages)) => true,
+    Some(RequestError::Api(api_error)) => api_error
+      .to_string()
+      .to_lowercase()
+      .contains("not enough rights"),
+    _ => false,
+  }
+}
+
+// True the first time a chat is reported, so the warning and notice aren't repeated per search
+pub fn first_notice(chat_id: ChatId) -> bool {
+  let mut chats = notified_chats();
+  if chats.contains(&chat_id) {
+    return false;
+  }
+  if chats.len() >= NOTIFIED_CHATS_CAPACITY {
+    chats.pop_front();
+  }
+  chats.push_back(chat_id);
+  true
+}
+
+fn notified_chats() -> MutexGuard<'static, VecDeque<ChatId>> {
+  NOTIFIED_CHATS
+    .get_or_init(|| Mutex::new(VecDeque::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use std::io::Cursor;
+
+use crate::image_tools::{record_decode_failure, run_image_job};
+
+pub const MAX_GRID_IMAGES: usize = 9;
+const CELL_SIZE: u32 = 320;
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+
+// Composite up to MAX_GRID_IMAGES encoded images, given as (source URL, bytes) pairs,
+// into a single JPEG contact sheet. Each image is scaled to fit a square cell (keeping
+// its aspect ratio) and centered in it. Images that fail to decode are skipped.
+pub fn build_montage(images: &[(String, Vec<u8>)]) -> Result<Vec<u8>, anyhow::Error> {
+  let decoded: Vec<DynamicImage> = images
+    .iter()
+    .take(MAX_GRID_IMAGES)
+    .filter_map(|(source, bytes)| match image::load_from_memory(bytes) {
+      Ok(img) => Some(img),
+      Err(e) => {
+        record_decode_failure(source, &e);
+        None
+      }
+    })
+    .collect();
+
+  if decoded.is_empty() {
+    return Err(anyhow!("No decodable images for montage"));
+  }
+
+  let (columns, rows) = grid_shape(decoded.len());
+  let mut canvas = RgbImage::from_pixel(columns * CELL_SIZE, rows * CELL_SIZE, BACKGROUND);
+
+  for (index, img) in decoded.iter().enumerate() {
+    let cell = img
+      .resize(CELL_SIZE, CELL_SIZE, FilterType::Triangle)
+      .to_rgb8();
+    let column = index as u32 % columns;
+    let row = index as u32 / columns;
+    let x = column * CELL_SIZE + (CELL_SIZE - cell.width()) / 2;
+    let y = row * CELL_SIZE + (CELL_SIZE - cell.height()) / 2;
+    imageops::overlay(&mut canvas, &cell, i64::from(x), i64::from(y));
+  }
+
+  let mut output = Vec::new();
+  DynamicImage::ImageRgb8(canvas).write_to(&mut Cursor::new(&mut output), ImageFormat::Jpeg)?;
+  Ok(output)
+}
+
+// Run build_montage off the async runtime since decoding and resizing are CPU-bound
+pub async fn build_montage_blocking(
+  images: Vec<(String, Vec<u8>)>,
+) -> Result<Vec<u8>, anyhow::Error> {
+  run_image_job(move || build_montage(&images)).await?
+}
+
+// Smallest near-square grid that fits `count` cells: columns = ceil(sqrt(count))
+fn grid_shape(count: usize) -> (u32, u32) {
+  let count = count as u32;
+  let mut columns = 1;
+  while columns * columns < count {
+    columns += 1;
+  }
+  (columns, count.div_ceil(columns))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut output = Vec::new();
+    DynamicImage::new_rgb8(width, height)
+      .write_to(&mut Cursor::new(&mut output), ImageFormat::Png)
+      .unwrap();
+    output
+  }
+
+  #[test]
+  fn montage_of_three_images_is_a_two_by_two_grid() {
+    let images: Vec<(String, Vec<u8>)> = [(40, 30), (10, 80), (64, 64)]
+      .into_iter()
+      .enumerate()
+      .map(|(index, (width, height))| (format!("image-{}", index), png(width, height)))
+      .collect();
+
+    let montage = image::load_from_memory(&build_montage(&images).unwrap()).unwrap();
+
+    assert_eq!(montage.width(), 2 * CELL_SIZE);
+    assert_eq!(montage.height(), 2 * CELL_SIZE);
+  }
+
+  #[test]
+  fn grid_shape_stays_near_square() {
+    assert_eq!(grid_shape(1), (1, 1));
+    assert_eq!(grid_shape(3), (2, 2));
+    assert_eq!(grid_shape(5), (3, 2));
+    assert_eq!(grid_shape(MAX_GRID_IMAGES), (3, 3));
+  }
+}
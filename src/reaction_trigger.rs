@@ -0,0 +1,62 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use teloxide::types::{ChatId, Message, MessageId, MessageReactionUpdated};
+
+use crate::config_file::setting;
+
+// Reaction updates only carry the message id, so recent texts are kept to search by reaction
+const RECENT_TEXT_CAPACITY: usize = 1000;
+static TRIGGER_EMOJI: OnceLock<Option<String>> = OnceLock::new();
+static RECENT_TEXTS: OnceLock<Mutex<VecDeque<(ChatId, MessageId, String)>>> = OnceLock::new();
+
+// Emoji that triggers a search when reacted or replied to a message, from REACTION_TRIGGER_EMOJI.
+// Unset disables the feature.
+pub fn trigger_emoji() -> Option<&'static str> {
+  TRIGGER_EMOJI
+    .get_or_init(|| {
+      setting("REACTION_TRIGGER_EMOJI")
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+    })
+    .as_deref()
+}
+
+// True when the emoji was just added (not already present before the update)
+pub fn is_trigger_reaction(reaction: &MessageReactionUpdated, emoji: &str) -> bool {
+  let has_emoji = |reactions: &[teloxide::types::ReactionType]| {
+    reactions
+      .iter()
+      .any(|reaction| reaction.emoji().map(String::as_str) == Some(emoji))
+  };
+
+  has_emoji(&reaction.new_reaction) && !has_emoji(&reaction.old_reaction)
+}
+
+// True when the message is just the trigger emoji sent as a reply
+pub fn is_trigger_reply(msg: &Message, emoji: &str) -> bool {
+  msg.reply_to_message().is_some() && msg.text().map(str::trim) == Some(emoji)
+}
+
+pub fn remember_text(chat_id: ChatId, message_id: MessageId, text: &str) {
+  let mut texts = recent_texts();
+  if texts.len() >= RECENT_TEXT_CAPACITY {
+    texts.pop_front();
+  }
+  texts.push_back((chat_id, message_id, text.to_string()));
+}
+
+pub fn recall_text(chat_id: ChatId, message_id: MessageId) -> Option<String> {
+  recent_texts()
+    .iter()
+    .rev()
+    .find(|(chat, message, _)| *chat == chat_id && *message == message_id)
+    .map(|(_, _, text)| text.clone())
+}
+
+fn recent_texts() -> MutexGuard<'static, VecDeque<(ChatId, MessageId, String)>> {
+  RECENT_TEXTS
+    .get_or_init(|| Mutex::new(VecDeque::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
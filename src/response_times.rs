@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+// Upper bounds of the histogram buckets; anything slower lands in a final overflow bucket
+const BUCKET_BOUNDS_MS: [u64; 7] = [100, 250, 500, 1000, 2500, 5000, 10000];
+static HISTOGRAMS: OnceLock<Mutex<HashMap<Branch, Histogram>>> = OnceLock::new();
+
+// Which path handled a request, so slow scraping can be told apart from slow inline answers
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Branch {
+  Featured,
+  Online,
+  Inline,
+}
+
+impl Branch {
+  pub const ALL: [Branch; 3] = [Branch::Featured, Branch::Online, Branch::Inline];
+
+  pub fn label(self) -> &'static str {
+    match self {
+      Branch::Featured => "Featured",
+      Branch::Online => "Online",
+      Branch::Inline => "Inline",
+    }
+  }
+}
+
+#[derive(Default)]
+struct Histogram {
+  counts: [u64; BUCKET_BOUNDS_MS.len() + 1],
+  total_ms: u64,
+}
+
+impl Histogram {
+  fn record(&mut self, elapsed: Duration) {
+    let millis = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+    let bucket = BUCKET_BOUNDS_MS
+      .iter()
+      .position(|bound| millis <= *bound)
+      .unwrap_or(BUCKET_BOUNDS_MS.len());
+    self.counts[bucket] += 1;
+    self.total_ms = self.total_ms.saturating_add(millis);
+  }
+
+  fn count(&self) -> u64 {
+    self.counts.iter().sum()
+  }
+
+  // e.g. "12 requests, avg 840 ms (<=100ms: 2, <=250ms: 5, ..., >10000ms: 1)"
+  fn summary(&self) -> String {
+    let count = self.count();
+    let buckets: Vec<_> = self
+      .counts
+      .iter()
+      .enumerate()
+      .filter(|(_, hits)| **hits > 0)
+      .map(|(bucket, hits)| match BUCKET_BOUNDS_MS.get(bucket) {
+        Some(bound) => format!("<={}ms: {}", bound, hits),
+        None => format!(
+          ">{}ms: {}",
+          BUCKET_BOUNDS_MS[BUCKET_BOUNDS_MS.len() - 1],
+          hits
+        ),
+      })
+      .collect();
+    format!(
+      "{} requests, avg {} ms ({})",
+      count,
+      self.total_ms / count.max(1),
+      buckets.join(", ")
+    )
+  }
+}
+
+// Add one handled request to its branch's histogram
+pub fn record_duration(branch: Branch, elapsed: Duration) {
+  histograms().entry(branch).or_default().record(elapsed);
+}
+
+// One line per branch that has handled a request since startup, for /stats
+pub fn describe_response_times() -> Vec<String> {
+  let histograms = histograms();
+  Branch::ALL
+    .into_iter()
+    .filter_map(|branch| {
+      let histogram = histograms.get(&branch)?;
+      Some(format!("{}: {}", branch.label(), histogram.summary()))
+    })
+    .collect()
+}
+
+fn histograms() -> MutexGuard<'static, HashMap<Branch, Histogram>> {
+  HISTOGRAMS
+    .get_or_init(|| Mutex::new(HashMap::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn durations_land_in_their_buckets() {
+    let mut histogram = Histogram::default();
+    histogram.record(Duration::from_millis(40));
+    histogram.record(Duration::from_millis(100));
+    histogram.record(Duration::from_millis(700));
+    histogram.record(Duration::from_secs(30));
+
+    assert_eq!(histogram.counts, [2, 0, 0, 1, 0, 0, 0, 1]);
+    assert_eq!(
+      histogram.summary(),
+      "4 requests, avg 7710 ms (<=100ms: 2, <=1000ms: 1, >10000ms: 1)"
+    );
+  }
+}
@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config_file::setting;
+
+// Telegram's documented global limit for bots
+const DEFAULT_GLOBAL_SEND_RATE: u32 = 30;
+static NEXT_SLOT: OnceLock<Mutex<Option<Instant>>> = OnceLock::new();
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+// Minimum spacing between outbound sends across all chats, from GLOBAL_SEND_RATE (per second, 0 disables)
+fn send_interval() -> Duration {
+  let rate = setting("GLOBAL_SEND_RATE")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(DEFAULT_GLOBAL_SEND_RATE);
+  if rate == 0 {
+    Duration::ZERO
+  } else {
+    Duration::from_secs(1) / rate
+  }
+}
+
+// Wait for this send's turn so bursts across many chats are spread out under the global rate
+pub async fn acquire() {
+  let interval = send_interval();
+  if interval.is_zero() {
+    return;
+  }
+
+  let slot = {
+    let mut next_slot = NEXT_SLOT
+      .get_or_init(|| Mutex::new(None))
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let now = Instant::now();
+    let slot = next_slot.map_or(now, |next| next.max(now));
+    *next_slot = Some(slot + interval);
+    slot
+  };
+  WAITING.fetch_add(1, Ordering::SeqCst);
+  tokio::time::sleep_until(slot).await;
+  WAITING.fetch_sub(1, Ordering::SeqCst);
+}
+
+// Sends currently waiting for their turn, and how long until the last of them goes out
+pub fn backlog() -> (usize, Duration) {
+  let delay = NEXT_SLOT
+    .get()
+    .and_then(|next_slot| {
+      *next_slot
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+    })
+    .map(|next| next.saturating_duration_since(Instant::now()))
+    .unwrap_or_default()
+    .saturating_sub(send_interval());
+  (WAITING.load(Ordering::SeqCst), delay)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Each test pauses its own runtime's clock, so the shared next slot is reset under a lock
+  async fn fresh_gate() -> tokio::sync::MutexGuard<'static, ()> {
+    static LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+    let guard = LOCK
+      .get_or_init(|| tokio::sync::Mutex::new(()))
+      .lock()
+      .await;
+    tokio::time::pause();
+    *NEXT_SLOT
+      .get_or_init(|| Mutex::new(None))
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+    guard
+  }
+
+  #[tokio::test]
+  async fn bursts_are_paced_to_the_global_rate() {
+    let _gate = fresh_gate().await;
+    let interval = send_interval();
+    assert_eq!(interval, Duration::from_secs(1) / DEFAULT_GLOBAL_SEND_RATE);
+
+    let started = Instant::now();
+    let sends: Vec<_> = (0..10).map(|_| tokio::spawn(acquire())).collect();
+    for send in sends {
+      send.await.unwrap();
+    }
+
+    // The first send goes out at once and each later one waits a full interval
+    let elapsed = started.elapsed();
+    assert!(elapsed >= interval * 9);
+    assert!(elapsed < interval * 11);
+  }
+
+  #[tokio::test]
+  async fn backlog_reflects_waiting_sends() {
+    let _gate = fresh_gate().await;
+    let interval = send_interval();
+
+    let sends: Vec<_> = (0..5).map(|_| tokio::spawn(acquire())).collect();
+    tokio::task::yield_now().await;
+
+    // The last of the five goes out four intervals from now
+    let (waiting, delay) = backlog();
+    assert!(waiting >= 4);
+    assert_eq!(delay, interval * 4);
+
+    for send in sends {
+      send.await.unwrap();
+    }
+    assert_eq!(backlog(), (0, Duration::ZERO));
+  }
+}
@@ -0,0 +1,115 @@
+use log::warn;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use url::Url;
+
+use crate::config_file::setting;
+
+static SEND_METHOD_OVERRIDES: OnceLock<HashMap<String, SendMethod>> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SendMethod {
+  Photo,
+  Animation,
+  Document,
+  Sticker,
+}
+
+impl SendMethod {
+  pub fn parse(value: &str) -> Option<Self> {
+    match value.trim().to_lowercase().as_str() {
+      "photo" => Some(SendMethod::Photo),
+      "animation" => Some(SendMethod::Animation),
+      "document" => Some(SendMethod::Document),
+      "sticker" => Some(SendMethod::Sticker),
+      _ => None,
+    }
+  }
+
+  pub fn label(self) -> &'static str {
+    match self {
+      SendMethod::Photo => "photo",
+      SendMethod::Animation => "animation",
+      SendMethod::Document => "document",
+      SendMethod::Sticker => "sticker",
+    }
+  }
+}
+
+// Extension -> send method overrides from SEND_METHODS, e.g. "png=document,webp=sticker"
+pub fn send_method_overrides() -> &'static HashMap<String, SendMethod> {
+  SEND_METHOD_OVERRIDES.get_or_init(|| {
+    setting("SEND_METHODS")
+      .map(|value| parse_send_methods(&value))
+      .unwrap_or_default()
+  })
+}
+
+fn parse_send_methods(value: &str) -> HashMap<String, SendMethod> {
+  value
+    .split(',')
+    .filter(|entry| !entry.trim().is_empty())
+    .filter_map(|entry| {
+      let parsed = entry.split_once('=').and_then(|(extension, method)| {
+        let extension = extension.trim().trim_start_matches('.').to_lowercase();
+        Some((extension, SendMethod::parse(method)?))
+      });
+      if parsed.is_none() {
+        warn!("Ignoring invalid SEND_METHODS entry: {}", entry.trim());
+      }
+      parsed
+    })
+    .collect()
+}
+
+// How to send an image: an override for the URL's extension, else animation for GIF
+// queries and photo otherwise
+pub fn send_method_for(
+  url: &str,
+  is_gif: bool,
+  overrides: &HashMap<String, SendMethod>,
+) -> SendMethod {
+  let extension = Url::parse(url).ok().and_then(|url| {
+    let path = url.path().to_lowercase();
+    let (_, extension) = path.rsplit_once('.')?;
+    Some(extension.to_string())
+  });
+
+  match extension.and_then(|extension| overrides.get(&extension)) {
+    Some(method) => *method,
+    None if is_gif => SendMethod::Animation,
+    None => SendMethod::Photo,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn extension_override_redirects_png_to_document() {
+    let overrides = parse_send_methods(".PNG=document, bogus, webp=sticker");
+    assert_eq!(overrides.len(), 2);
+    assert_eq!(
+      send_method_for("https://example.com/logo.png", false, &overrides),
+      SendMethod::Document
+    );
+    assert_eq!(
+      send_method_for("https://example.com/cat.jpg", false, &overrides),
+      SendMethod::Photo
+    );
+  }
+
+  #[test]
+  fn default_routing_without_overrides() {
+    let overrides = HashMap::new();
+    assert_eq!(
+      send_method_for("https://example.com/dance.gif", true, &overrides),
+      SendMethod::Animation
+    );
+    assert_eq!(
+      send_method_for("https://example.com/cat.jpg", false, &overrides),
+      SendMethod::Photo
+    );
+  }
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config_file::setting;
+
+const DEFAULT_SHUTDOWN_DRAIN_SECS: u64 = 5;
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+// Held while one update is being handled, so shutdown knows what is still running
+pub struct InFlight(());
+
+impl InFlight {
+  pub fn start() -> Self {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    InFlight(())
+  }
+}
+
+impl Drop for InFlight {
+  fn drop(&mut self) {
+    IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+pub fn in_flight() -> usize {
+  IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+// How long in-flight handlers may keep running after Ctrl-C, from SHUTDOWN_DRAIN_SECS
+pub fn drain_timeout() -> Duration {
+  let secs = setting("SHUTDOWN_DRAIN_SECS")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(DEFAULT_SHUTDOWN_DRAIN_SECS);
+  Duration::from_secs(secs)
+}
+
+// Wait until nothing is in flight or the timeout passes; returns how many are still running
+pub async fn drain(timeout: Duration) -> usize {
+  let deadline = Instant::now() + timeout;
+  while in_flight() > 0 && Instant::now() < deadline {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+  in_flight()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn in_flight_task_finishes_within_the_drain_window() {
+    tokio::time::pause();
+    let task = InFlight::start();
+    let handler = tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_secs(1)).await;
+      drop(task);
+    });
+
+    assert_eq!(drain(Duration::from_secs(5)).await, 0);
+    handler.await.unwrap();
+  }
+}
@@ -0,0 +1,79 @@
+use std::sync::OnceLock;
+
+use crate::config_file::setting;
+
+static STOPWORDS: OnceLock<Vec<String>> = OnceLock::new();
+
+// Noise words stripped from queries before searching, read from STOPWORDS (comma-separated)
+pub fn stopwords() -> &'static [String] {
+  STOPWORDS.get_or_init(|| {
+    setting("STOPWORDS")
+      .map(|value| {
+        value
+          .split(',')
+          .map(|word| word.trim().to_lowercase())
+          .filter(|word| !word.is_empty())
+          .collect()
+      })
+      .unwrap_or_default()
+  })
+}
+
+// Remove stopwords from the query, e.g. "來張 cat" -> "cat".
+// Whole words are dropped case-insensitively; CJK stopwords are also stripped as a
+// prefix since zh-TW queries often omit the space ("來張貓" -> "貓").
+// Falls back to the original query if nothing would be left.
+pub fn strip_stopwords(query: &str, stopwords: &[String]) -> String {
+  if stopwords.is_empty() {
+    return query.to_string();
+  }
+
+  let words: Vec<&str> = query
+    .split_whitespace()
+    .filter(|word| !stopwords.contains(&word.to_lowercase()))
+    .map(|word| strip_cjk_prefix(word, stopwords))
+    .filter(|word| !word.is_empty())
+    .collect();
+
+  if words.is_empty() {
+    query.to_string()
+  } else {
+    words.join(" ")
+  }
+}
+
+fn strip_cjk_prefix<'a>(word: &'a str, stopwords: &[String]) -> &'a str {
+  stopwords
+    .iter()
+    .filter(|stopword| !stopword.is_ascii())
+    .find_map(|stopword| word.strip_prefix(stopword.as_str()))
+    .unwrap_or(word)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn list(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+  }
+
+  #[test]
+  fn stopwords_are_removed_ignoring_case() {
+    let stopwords = list(&["please", "來張"]);
+    assert_eq!(strip_stopwords("PLEASE cat", &stopwords), "cat");
+    assert_eq!(strip_stopwords("來張 cat", &stopwords), "cat");
+    assert_eq!(strip_stopwords("來張貓", &stopwords), "貓");
+  }
+
+  #[test]
+  fn query_of_only_stopwords_is_kept() {
+    let stopwords = list(&["please", "來張"]);
+    assert_eq!(strip_stopwords("Please 來張", &stopwords), "Please 來張");
+  }
+
+  #[test]
+  fn empty_list_leaves_query_alone() {
+    assert_eq!(strip_stopwords("please cat", &[]), "please cat");
+  }
+}
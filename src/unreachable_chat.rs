@@ -0,0 +1,41 @@
+use teloxide::{ApiError, RequestError};
+
+// Whether a send failed because the bot can no longer post to the chat at all: the user
+// blocked it, or it was removed from the group or the group is gone. No later candidate
+// can succeed, so callers stop instead of trying each one.
+pub fn is_chat_unreachable(error: &anyhow::Error) -> bool {
+  match error.downcast_ref::<RequestError>() {
+    Some(RequestError::Api(
+      ApiError::BotBlocked
+      | ApiError::ChatNotFound
+      | ApiError::BotKicked
+      | ApiError::BotKickedFromSupergroup
+      | ApiError::GroupDeactivated
+      | ApiError::UserDeactivated,
+    )) => true,
+    // e.g. "Forbidden: bot was kicked from the channel chat", which has no variant
+    Some(RequestError::Api(ApiError::Unknown(message))) => {
+      let message = message.to_lowercase();
+      message.contains("bot was blocked") || message.contains("bot was kicked")
+    }
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn blocked_or_removed_bot_is_terminal() {
+    let api = |error: ApiError| anyhow::Error::from(RequestError::Api(error));
+    assert!(is_chat_unreachable(&api(ApiError::BotBlocked)));
+    assert!(is_chat_unreachable(&api(ApiError::ChatNotFound)));
+    assert!(is_chat_unreachable(&api(ApiError::Unknown(
+      "Forbidden: bot was kicked from the channel chat".to_string()
+    ))));
+
+    assert!(!is_chat_unreachable(&api(ApiError::WrongFileIdOrUrl)));
+    assert!(!is_chat_unreachable(&anyhow::anyhow!("bot was blocked")));
+  }
+}
@@ -0,0 +1,99 @@
+use log::warn;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+use teloxide::types::UserId;
+
+use crate::config_file::setting;
+
+// Buckets of users idle long enough to be full again are dropped past this many
+const BUCKETS_CAPACITY: usize = 10_000;
+static BUCKETS: OnceLock<Mutex<HashMap<UserId, Bucket>>> = OnceLock::new();
+
+// Token bucket refilled continuously at the configured rate, holding at most a minute's worth
+struct Bucket {
+  tokens: f64,
+  refilled: Instant,
+}
+
+// Searches one user may trigger per minute across all chats, from USER_SEARCH_RATE (0 or unset disables)
+fn searches_per_minute() -> u32 {
+  setting("USER_SEARCH_RATE")
+    .ok()
+    .and_then(|value| value.trim().parse().ok())
+    .unwrap_or(0)
+}
+
+// Take a token for this user's search; false means they are over their rate and the
+// trigger should be ignored. Other users, including those in the same chat, are unaffected.
+pub fn allow_search(user_id: UserId) -> bool {
+  let rate = searches_per_minute();
+  if rate == 0 {
+    return true;
+  }
+
+  if take_token(&mut buckets(), user_id, rate, Instant::now()) {
+    return true;
+  }
+  warn!("Throttled search from user {}", user_id);
+  false
+}
+
+fn take_token(
+  buckets: &mut HashMap<UserId, Bucket>,
+  user_id: UserId,
+  rate: u32,
+  now: Instant,
+) -> bool {
+  let capacity = f64::from(rate);
+  let per_second = capacity / 60.0;
+  if buckets.len() >= BUCKETS_CAPACITY && !buckets.contains_key(&user_id) {
+    buckets.retain(|_, bucket| {
+      bucket.tokens + now.duration_since(bucket.refilled).as_secs_f64() * per_second < capacity
+    });
+  }
+
+  let bucket = buckets.entry(user_id).or_insert(Bucket {
+    tokens: capacity,
+    refilled: now,
+  });
+  let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+  bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+  bucket.refilled = now;
+
+  if bucket.tokens < 1.0 {
+    return false;
+  }
+  bucket.tokens -= 1.0;
+  true
+}
+
+fn buckets() -> MutexGuard<'static, HashMap<UserId, Bucket>> {
+  BUCKETS
+    .get_or_init(|| Mutex::new(HashMap::new()))
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn spamming_user_is_throttled_while_others_search() {
+    let mut buckets = HashMap::new();
+    let (spammer, neighbour) = (UserId(1), UserId(2));
+    let start = Instant::now();
+
+    for _ in 0..3 {
+      assert!(take_token(&mut buckets, spammer, 3, start));
+    }
+    assert!(!take_token(&mut buckets, spammer, 3, start));
+    assert!(take_token(&mut buckets, neighbour, 3, start));
+
+    // At 3 per minute a token comes back every 20 seconds
+    let later = start + std::time::Duration::from_secs(21);
+    assert!(take_token(&mut buckets, spammer, 3, later));
+    assert!(!take_token(&mut buckets, spammer, 3, later));
+  }
+}